@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use dungeonctl::{
     Coyote3, Stereo,
-    coyote3::{DeviceSettings, IntensityChange, Pulse, Pulses},
+    coyote3::{DeviceSettings, Envelope, Frequency, IntensityChange, Pulses, WaveformBuilder},
 };
 use futures_signals::signal::SignalExt;
 use tracing::info;
@@ -33,40 +33,27 @@ async fn main() -> eyre::Result<()> {
                     a: IntensityChange::AbsoluteChange(20),
                     b: IntensityChange::AbsoluteChange(0),
                 },
-                pulses: [Stereo {
-                    a: Pulse {
-                        frequency: 0,
-                        intensity: 0,
-                    },
-                    b: Pulse {
-                        frequency: 0,
-                        intensity: 0,
-                    },
-                }; 4],
+                ..Pulses::silent()
             })
             .await?;
 
+        let amplitude = |i: u64| {
+            50 + (50.0 * ((std::f32::consts::TAU * (i as f32) / 20.0).sin() / 2.0 + 0.5)) as u8
+        };
+
         for i in 0u64.. {
             coyote
-                .send_pulses(Pulses {
-                    intensity: Stereo {
-                        a: IntensityChange::DoNotChange,
-                        b: IntensityChange::DoNotChange,
-                    },
-                    pulses: [Stereo {
-                        a: Pulse {
-                            frequency: 200,
-                            intensity: 50
-                                + (50.0
-                                    * ((std::f32::consts::TAU * (i as f32) / 20.0).sin() / 2.0
-                                        + 0.5)) as u8,
-                        },
-                        b: Pulse {
-                            frequency: 0,
-                            intensity: 0,
-                        },
-                    }; 4],
-                })
+                .send_pulses(
+                    WaveformBuilder::new()
+                        .a(
+                            Frequency::hz(200)?,
+                            Envelope::Linear {
+                                from: amplitude(i),
+                                to: amplitude(i + 1),
+                            },
+                        )
+                        .build(),
+                )
                 .await?;
 
             tokio::time::sleep(Duration::from_millis(100)).await;
@@ -76,7 +63,7 @@ async fn main() -> eyre::Result<()> {
     }
 
     tokio::select!(
-        _ = coyote.state().for_each(async |state| info!(?state)) => Ok(()),
+        _ = coyote.state().for_each(async |state| info!(%state)) => Ok(()),
         res = async {
             stim(&coyote).await?;
 
@@ -0,0 +1,40 @@
+use std::future::Future;
+
+use super::{StateSignal, StimGuard};
+use crate::Result;
+
+/// The surface shared by all supported devices.
+///
+/// This allows writing device-agnostic code, or control logic that can be tested without a
+/// device using a mock.
+#[allow(private_bounds)]
+pub trait Device: super::Sealed {
+    /// The state reported by the device.
+    type State;
+    /// The output data that is periodically sent to the device.
+    type Pulses;
+    /// The device settings.
+    type Settings;
+
+    /// Get the state of the device as a reactive signal.
+    fn state(&self) -> impl StateSignal<Self::State> + Send + use<Self>;
+    /// Send the next output data to the device.
+    fn send_pulses(&self, pulses: Self::Pulses) -> impl Future<Output = Result<()>> + Send;
+    /// Update the device settings.
+    fn update_settings(&self, settings: Self::Settings) -> impl Future<Output = Result<()>> + Send;
+    /// Stop any output immediately.
+    fn stop(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Stop any output and disconnect from the device.
+    fn disconnect(&self) -> impl Future<Output = Result<()>> + Send;
+    /// Get a guard that stops the output once it is dropped, e.g. when the task controlling the
+    /// device panics.
+    ///
+    /// The output is only stopped if the guard is dropped within a Tokio runtime, see
+    /// [`StimGuard`] for the limitations.
+    fn guard(&self) -> StimGuard<Self>
+    where
+        Self: Clone + Send + Sync + 'static,
+    {
+        StimGuard::new(self.clone())
+    }
+}
@@ -0,0 +1,61 @@
+use tracing::error;
+
+use super::Device;
+
+/// Stops the output of a device when dropped. This is created by [`Device::guard()`].
+///
+/// Since Rust has no async drop, dropping the guard spawns a detached Tokio task that sends the
+/// stop command. This is a best-effort safety net for when a task panics or returns early: the
+/// command is not sent if there is no Tokio runtime or the runtime shuts down before the task
+/// ran. Prefer calling [`Device::stop()`] explicitly where possible and use
+/// [`disarm()`](Self::disarm) afterwards.
+///
+/// The guard dereferences to the device.
+#[must_use = "the output is stopped as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct StimGuard<D: Device + Send + Sync + 'static> {
+    device: Option<D>,
+}
+
+impl<D: Device + Send + Sync + 'static> StimGuard<D> {
+    pub(super) fn new(device: D) -> Self {
+        Self {
+            device: Some(device),
+        }
+    }
+    /// Give back the device without stopping its output.
+    pub fn disarm(mut self) -> D {
+        self.device
+            .take()
+            .expect("only taken when disarming or dropping")
+    }
+}
+
+impl<D: Device + Send + Sync + 'static> std::ops::Deref for StimGuard<D> {
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        self.device
+            .as_ref()
+            .expect("only taken when disarming or dropping")
+    }
+}
+
+impl<D: Device + Send + Sync + 'static> Drop for StimGuard<D> {
+    fn drop(&mut self) {
+        let Some(device) = self.device.take() else {
+            return;
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = device.stop().await {
+                        error!(?e, "failed to stop output");
+                    }
+                });
+            }
+            Err(_) => error!("stim guard dropped outside of a Tokio runtime, output not stopped"),
+        }
+    }
+}
@@ -1,8 +1,23 @@
+mod device;
+mod guard;
 mod peripheral;
 mod state;
 mod stereo;
+mod timer;
 
-trait Sealed {}
+pub(crate) trait Sealed {}
 
-pub(crate) use self::{peripheral::PeripheralExt, state::DeviceState};
-pub use self::{state::StateSignal, stereo::Stereo};
+#[cfg(any(test, feature = "mock"))]
+pub(crate) use self::state::StateSource;
+pub use self::{
+    device::Device,
+    guard::StimGuard,
+    state::StateSignal,
+    stereo::{Channel, Stereo},
+    timer::{Timer, TokioTimer},
+};
+pub(crate) use self::{
+    peripheral::{CentralExt, PeripheralExt, PeripheralFilter},
+    state::DeviceState,
+    timer::{Interval, sleep, timeout},
+};
@@ -1,11 +1,354 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use btleplug::api::{BDAddr, Central, PeripheralProperties, ScanFilter};
+use tracing::error;
+
+/// Describes which peripherals to accept while scanning.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PeripheralFilter<'a> {
+    pub(crate) local_name: &'a str,
+    pub(crate) address: Option<BDAddr>,
+}
+
+impl PeripheralFilter<'_> {
+    pub(crate) fn matches(&self, properties: &PeripheralProperties) -> bool {
+        properties.local_name.as_deref() == Some(self.local_name)
+            && self
+                .address
+                .is_none_or(|address| address == properties.address)
+    }
+}
+
 pub(crate) trait PeripheralExt: btleplug::api::Peripheral {
-    async fn local_name_matches(&self, name: &str) -> btleplug::Result<bool> {
+    async fn matches(&self, filter: &PeripheralFilter<'_>) -> btleplug::Result<bool> {
         let properties = self.properties().await?;
 
-        Ok(properties
-            .and_then(|p| p.local_name)
-            .is_some_and(|local_name| local_name == name))
+        Ok(properties.is_some_and(|properties| filter.matches(&properties)))
     }
 }
 
 impl<T: btleplug::api::Peripheral> PeripheralExt for T {}
+
+/// The scans in progress per adapter, see [`CentralExt`].
+static SCANS: LazyLock<futures::lock::Mutex<HashMap<String, Scan>>> =
+    LazyLock::new(Default::default);
+
+/// A scan shared by `users` concurrent users of the same adapter.
+#[derive(Debug)]
+struct Scan {
+    users: usize,
+    filter: ScanFilter,
+}
+
+/// Merge two scan filters into one that lets through any device either of them does.
+fn merge_filters(a: &ScanFilter, b: &ScanFilter) -> ScanFilter {
+    // an empty filter lets through everything
+    if a.services.is_empty() || b.services.is_empty() {
+        return ScanFilter::default();
+    }
+
+    let mut services = a.services.clone();
+    for service in &b.services {
+        if !services.contains(service) {
+            services.push(*service);
+        }
+    }
+
+    ScanFilter { services }
+}
+
+/// Scanning that is shared between concurrent users of the same adapter.
+///
+/// Connecting to several devices at once scans on the same adapter concurrently. Stopping the
+/// scan as soon as one of them found its device would leave the others waiting forever, so the
+/// scan is only stopped once every user stopped it. If the users pass different filters, the
+/// scan is restarted with a filter that lets through the devices of all of them.
+pub(crate) trait CentralExt: Central + 'static {
+    /// Start scanning or join the scan in progress, until the returned [`SharedScan`] is stopped
+    /// or dropped.
+    async fn start_shared_scan(&self, filter: ScanFilter) -> btleplug::Result<SharedScan<Self>> {
+        let adapter = self.adapter_info().await?;
+        let mut scans = SCANS.lock().await;
+
+        match scans.get_mut(&adapter) {
+            Some(scan) => {
+                let merged = merge_filters(&scan.filter, &filter);
+                if merged != scan.filter {
+                    self.stop_scan().await?;
+                    self.start_scan(merged.clone()).await?;
+                    scan.filter = merged;
+                }
+                scan.users += 1;
+            }
+            None => {
+                self.start_scan(filter.clone()).await?;
+                scans.insert(adapter, Scan { users: 1, filter });
+            }
+        }
+
+        Ok(SharedScan {
+            central: Some(self.clone()),
+        })
+    }
+    /// Start the shared scan again, e.g. after the adapter was reset and stopped scanning.
+    async fn restart_shared_scan(&self) -> btleplug::Result<()> {
+        let adapter = self.adapter_info().await?;
+        let scans = SCANS.lock().await;
+
+        if let Some(scan) = scans.get(&adapter) {
+            self.start_scan(scan.filter.clone()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Central + 'static> CentralExt for T {}
+
+/// A user of the shared scan of an adapter, see [`CentralExt`].
+///
+/// If it is dropped without being [stopped](Self::stop), e.g. because the future scanning was
+/// cancelled, it is stopped by a task spawned onto the Tokio runtime, so the scan doesn't keep
+/// running forever.
+#[must_use = "the scan keeps running until this is stopped"]
+pub(crate) struct SharedScan<C: Central + 'static> {
+    /// The adapter scanning, `None` once the scan was stopped.
+    central: Option<C>,
+}
+
+impl<C: Central + 'static> SharedScan<C> {
+    /// Leave the scan, stopping it if this was its last user.
+    pub(crate) async fn stop(mut self) -> btleplug::Result<()> {
+        let central = self.central.take().expect("only stopped once");
+        stop_shared_scan(&central).await
+    }
+}
+
+impl<C: Central + 'static> Drop for SharedScan<C> {
+    fn drop(&mut self) {
+        let Some(central) = self.central.take() else {
+            return;
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = stop_shared_scan(&central).await {
+                        error!(?e, "failed to stop scan");
+                    }
+                });
+            }
+            Err(_) => error!("scan dropped outside of a Tokio runtime, scan not stopped"),
+        }
+    }
+}
+
+/// Remove a user from the shared scan of `central`, stopping the scan if it was the last one.
+async fn stop_shared_scan(central: &impl Central) -> btleplug::Result<()> {
+    let adapter = central.adapter_info().await?;
+    let mut scans = SCANS.lock().await;
+    let Some(scan) = scans.get_mut(&adapter) else {
+        return Ok(());
+    };
+
+    scan.users -= 1;
+    if scan.users == 0 {
+        scans.remove(&adapter);
+        central.stop_scan().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        pin::Pin,
+        sync::{Arc, Mutex},
+    };
+
+    use btleplug::{
+        api::{CentralEvent, CentralState},
+        platform::{Peripheral, PeripheralId},
+    };
+    use futures::{FutureExt, Stream, future::BoxFuture};
+    use uuid::uuid;
+
+    /// An adapter that only keeps track of whether it is scanning.
+    #[derive(Clone)]
+    struct ScanningCentral {
+        name: &'static str,
+        scanning: Arc<Mutex<bool>>,
+    }
+
+    impl ScanningCentral {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                scanning: Default::default(),
+            }
+        }
+        fn is_scanning(&self) -> bool {
+            *self.scanning.lock().unwrap()
+        }
+    }
+
+    // the signatures are the ones generated by `async_trait`
+    impl Central for ScanningCentral {
+        type Peripheral = Peripheral;
+
+        fn events<'a, 'b>(
+            &'a self,
+        ) -> BoxFuture<'b, btleplug::Result<Pin<Box<dyn Stream<Item = CentralEvent> + Send>>>>
+        where
+            'a: 'b,
+            Self: 'b,
+        {
+            unimplemented!()
+        }
+        fn start_scan<'a, 'b>(&'a self, _filter: ScanFilter) -> BoxFuture<'b, btleplug::Result<()>>
+        where
+            'a: 'b,
+            Self: 'b,
+        {
+            *self.scanning.lock().unwrap() = true;
+            std::future::ready(Ok(())).boxed()
+        }
+        fn stop_scan<'a, 'b>(&'a self) -> BoxFuture<'b, btleplug::Result<()>>
+        where
+            'a: 'b,
+            Self: 'b,
+        {
+            *self.scanning.lock().unwrap() = false;
+            std::future::ready(Ok(())).boxed()
+        }
+        fn peripherals<'a, 'b>(&'a self) -> BoxFuture<'b, btleplug::Result<Vec<Peripheral>>>
+        where
+            'a: 'b,
+            Self: 'b,
+        {
+            unimplemented!()
+        }
+        fn peripheral<'a, 'b, 'c>(
+            &'a self,
+            _id: &'b PeripheralId,
+        ) -> BoxFuture<'c, btleplug::Result<Peripheral>>
+        where
+            'a: 'c,
+            'b: 'c,
+            Self: 'c,
+        {
+            unimplemented!()
+        }
+        fn add_peripheral<'a, 'b, 'c>(
+            &'a self,
+            _address: &'b PeripheralId,
+        ) -> BoxFuture<'c, btleplug::Result<Peripheral>>
+        where
+            'a: 'c,
+            'b: 'c,
+            Self: 'c,
+        {
+            unimplemented!()
+        }
+        fn adapter_info<'a, 'b>(&'a self) -> BoxFuture<'b, btleplug::Result<String>>
+        where
+            'a: 'b,
+            Self: 'b,
+        {
+            std::future::ready(Ok(self.name.to_owned())).boxed()
+        }
+        fn adapter_state<'a, 'b>(&'a self) -> BoxFuture<'b, btleplug::Result<CentralState>>
+        where
+            'a: 'b,
+            Self: 'b,
+        {
+            unimplemented!()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_scan() {
+        let central = ScanningCentral::new("shared");
+
+        let first = central
+            .start_shared_scan(ScanFilter::default())
+            .await
+            .unwrap();
+        let second = central
+            .start_shared_scan(ScanFilter::default())
+            .await
+            .unwrap();
+        first.stop().await.unwrap();
+        assert!(central.is_scanning());
+        second.stop().await.unwrap();
+        assert!(!central.is_scanning());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_scan() {
+        let central = ScanningCentral::new("cancelled");
+        let other = central
+            .start_shared_scan(ScanFilter::default())
+            .await
+            .unwrap();
+
+        // a connection attempt that is cancelled while scanning
+        let connect = async {
+            let scan = central.start_shared_scan(ScanFilter::default()).await?;
+            std::future::pending::<()>().await;
+            scan.stop().await
+        };
+        assert!(connect.now_or_never().is_none());
+        tokio::task::yield_now().await;
+
+        // the cancelled user left the scan, so the last one stops it
+        assert!(central.is_scanning());
+        other.stop().await.unwrap();
+        assert!(!central.is_scanning());
+        assert!(!SCANS.lock().await.contains_key("cancelled"));
+    }
+
+    #[test]
+    fn test_filter_address() {
+        let properties = |local_name: &str, address: [u8; 6]| PeripheralProperties {
+            address: address.into(),
+            local_name: Some(local_name.to_owned()),
+            ..Default::default()
+        };
+        let any = PeripheralFilter {
+            local_name: "47L121000",
+            address: None,
+        };
+        let specific = PeripheralFilter {
+            address: Some([1, 2, 3, 4, 5, 6].into()),
+            ..any
+        };
+
+        assert!(any.matches(&properties("47L121000", [1, 2, 3, 4, 5, 6])));
+        assert!(any.matches(&properties("47L121000", [6, 5, 4, 3, 2, 1])));
+        assert!(!any.matches(&properties("47L120100", [1, 2, 3, 4, 5, 6])));
+        assert!(specific.matches(&properties("47L121000", [1, 2, 3, 4, 5, 6])));
+        assert!(!specific.matches(&properties("47L121000", [6, 5, 4, 3, 2, 1])));
+        assert!(!specific.matches(&properties("47L120100", [1, 2, 3, 4, 5, 6])));
+    }
+
+    #[test]
+    fn test_merge_filters() {
+        let filter = |services: &[uuid::Uuid]| ScanFilter {
+            services: services.to_vec(),
+        };
+        let x = uuid!("0000180C-0000-1000-8000-00805f9b34fb");
+        let y = uuid!("0000180A-0000-1000-8000-00805f9b34fb");
+
+        assert_eq!(merge_filters(&filter(&[x]), &filter(&[x])), filter(&[x]));
+        assert_eq!(
+            merge_filters(&filter(&[x]), &filter(&[y, x])),
+            filter(&[x, y])
+        );
+        assert_eq!(merge_filters(&filter(&[x]), &filter(&[])), filter(&[]));
+        assert_eq!(merge_filters(&filter(&[]), &filter(&[y])), filter(&[]));
+    }
+}
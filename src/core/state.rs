@@ -1,70 +1,474 @@
-use std::{
-    pin::{Pin, pin},
-    sync::{Arc, Mutex, RwLock},
-};
+use std::{pin::Pin, sync::Arc, task::Context};
 
 use futures::{Stream, StreamExt};
-use futures_signals::signal::Signal;
+use futures_signals::signal::{Mutable, MutableSignalCloned, ReadOnlyMutable, Signal};
 
 /// A reactive [`Signal`] whose value can also be read out directly.
 ///
 /// [`Signal`]: https://docs.rs/futures-signals/latest/futures_signals/tutorial/index.html#signal-1
 #[allow(private_bounds)]
-pub trait StateSignal<T>: Signal<Item = T> + super::Sealed {
+pub trait StateSignal<T>: Signal<Item = T> + super::Sealed + Resubscribe {
     /// Get the current value.
     fn get(&self) -> T;
+    /// Get a new signal that starts with the current value, like
+    /// [`Mutable::signal_cloned()`].
+    ///
+    /// A clone of the signal, in contrast, only reports the changes that happen after it was
+    /// created.
+    ///
+    /// [`Mutable::signal_cloned()`]: https://docs.rs/futures-signals/latest/futures_signals/signal/struct.Mutable.html#method.signal_cloned
+    fn signal_cloned(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.resubscribe()
+    }
+}
+
+/// Backs [`StateSignal::signal_cloned()`], so the method can be provided by the public trait.
+pub(crate) trait Resubscribe {
+    /// Get a new signal of the same state that starts with the current value.
+    fn resubscribe(&self) -> Self
+    where
+        Self: Sized;
+}
+
+/// The side of a [`DeviceState`] that feeds it new values.
+///
+/// The signals of the state end once all clones of its source are dropped.
+#[derive(Clone, Debug)]
+pub(crate) struct StateSource<T>(Mutable<T>);
+
+impl<T: PartialEq> StateSource<T> {
+    /// Set the current value, waking up the signals if it changed.
+    pub(crate) fn set(&self, value: T) {
+        self.0.set_neq(value);
+    }
 }
 
-/// A reactive [`StateSignal`] that can be created from a stream of value updates.
-#[derive(Clone)]
+/// A reactive [`StateSignal`] whose value is kept in a [`Mutable`].
+///
+/// The value is set through a [`StateSource`], e.g. by a task applying a stream of updates as
+/// they arrive, see [`spawn()`](Self::spawn). Reading it never drives that stream, so
+/// [`get()`](StateSignal::get) only takes the read lock of the [`Mutable`], and each clone
+/// tracks the changes through its own signal of the [`Mutable`].
 pub(crate) struct DeviceState<T> {
-    stream: Arc<Mutex<dyn Stream<Item = T> + Send + Unpin + 'static>>,
-    inner: Arc<RwLock<T>>,
+    value: ReadOnlyMutable<T>,
+    changes: MutableSignalCloned<T>,
+    /// The last value reported by `changes`. A signal of a [`Mutable`] can report a value again
+    /// if it changes while the signal reads it, so the repetition is skipped.
+    reported: Option<T>,
+    /// The task feeding the state, if it was spawned for it.
+    task: Option<Arc<FeedTask>>,
+}
+
+/// Aborts the task feeding a [`DeviceState`] once its last clone is dropped.
+struct FeedTask(tokio::task::JoinHandle<()>);
+
+impl Drop for FeedTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
 }
 
 impl<T: Clone> DeviceState<T> {
-    pub(crate) fn new(stream: impl Stream<Item = T> + Send + Unpin + 'static, default: T) -> Self {
+    /// Create a state starting at `default`, along with the source to set its value.
+    pub(crate) fn new(default: T) -> (StateSource<T>, Self) {
+        let value = Mutable::new(default);
+        let (changes, reported) = changes(&value.read_only(), false);
+
+        let state = Self {
+            value: value.read_only(),
+            changes,
+            reported,
+            task: None,
+        };
+        (StateSource(value), state)
+    }
+    /// Create a state starting at `default` that takes on the values of `stream`, applied by a
+    /// task spawned onto the Tokio runtime. The signals end with the stream.
+    pub(crate) fn spawn(stream: impl Stream<Item = T> + Send + 'static, default: T) -> Self
+    where
+        T: PartialEq + Send + Sync + 'static,
+    {
+        let (source, mut state) = Self::new(default);
+        let task = tokio::spawn(stream.for_each(move |value| {
+            source.set(value);
+            std::future::ready(())
+        }));
+
+        state.task = Some(Arc::new(FeedTask(task)));
+        state
+    }
+    /// Derive a signal from this state that only changes when the mapped value changes.
+    pub(crate) fn derive<U>(&self, map: fn(&T) -> U) -> DerivedState<T, U> {
+        let last = map(&self.value.lock_ref());
+        DerivedState {
+            source: self.clone(),
+            map,
+            last: Some(last),
+        }
+    }
+    /// A clone with its own signal of the value, see [`changes()`].
+    fn with_changes(&self, report_current: bool) -> Self {
+        let (changes, reported) = changes(&self.value, report_current);
+
         Self {
-            stream: Arc::new(Mutex::from(stream)),
-            inner: Arc::new(RwLock::new(default)),
+            value: self.value.clone(),
+            changes,
+            reported,
+            task: self.task.clone(),
         }
     }
 }
 
-impl<T: Clone + std::fmt::Debug> std::fmt::Debug for DeviceState<T> {
+/// A new signal of `value` and the value it reported already. Unless `report_current` is set,
+/// the current value is marked as reported, so the signal only reports later changes.
+fn changes<T: Clone>(
+    value: &ReadOnlyMutable<T>,
+    report_current: bool,
+) -> (MutableSignalCloned<T>, Option<T>) {
+    let mut signal = value.signal_cloned();
+    if report_current {
+        return (signal, None);
+    }
+
+    // a fresh signal reports the current value right away
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    match Pin::new(&mut signal).poll_change(&mut cx) {
+        std::task::Poll::Ready(current) => (signal, current),
+        std::task::Poll::Pending => (signal, None),
+    }
+}
+
+impl<T: Clone> Clone for DeviceState<T> {
+    /// The clone only reports changes that happen after it was created.
+    fn clone(&self) -> Self {
+        self.with_changes(false)
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for DeviceState<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_tuple("DeviceState").field(&self.inner).finish()
+        f.debug_tuple("DeviceState")
+            .field(&*self.value.lock_ref())
+            .finish()
     }
 }
 
 impl<T> super::Sealed for DeviceState<T> {}
 
+impl<T: Clone> Resubscribe for DeviceState<T> {
+    fn resubscribe(&self) -> Self {
+        self.with_changes(true)
+    }
+}
+
 impl<T: Clone + PartialEq + Unpin> StateSignal<T> for DeviceState<T> {
     fn get(&self) -> T {
-        self.inner.read().unwrap().clone()
+        self.value.get_cloned()
     }
 }
 
 impl<T: Clone + PartialEq + Unpin> Signal for DeviceState<T> {
     type Item = T;
 
+    fn poll_change(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.changes).poll_change(cx) {
+                std::task::Poll::Ready(Some(value)) => {
+                    if self.reported.as_ref() != Some(&value) {
+                        self.reported = Some(value.clone());
+                        return std::task::Poll::Ready(Some(value));
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+/// A [`StateSignal`] derived from a [`DeviceState`] that only changes when the mapped value
+/// changes.
+pub(crate) struct DerivedState<T, U> {
+    source: DeviceState<T>,
+    map: fn(&T) -> U,
+    /// The last reported value, `None` if the current value is yet to be reported.
+    last: Option<U>,
+}
+
+impl<T: Clone, U: Clone> Clone for DerivedState<T, U> {
+    fn clone(&self) -> Self {
+        Self {
+            source: self.source.clone(),
+            map: self.map,
+            last: self.last.clone(),
+        }
+    }
+}
+
+impl<T, U: std::fmt::Debug> std::fmt::Debug for DerivedState<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("DerivedState")
+            .field(&(self.map)(&self.source.value.lock_ref()))
+            .finish()
+    }
+}
+
+impl<T, U> super::Sealed for DerivedState<T, U> {}
+
+impl<T: Clone + PartialEq + Unpin, U: Clone + PartialEq + Unpin> StateSignal<U>
+    for DerivedState<T, U>
+{
+    fn get(&self) -> U {
+        (self.map)(&self.source.value.lock_ref())
+    }
+}
+
+impl<T: Clone, U> Resubscribe for DerivedState<T, U> {
+    fn resubscribe(&self) -> Self {
+        Self {
+            source: self.source.resubscribe(),
+            map: self.map,
+            last: None,
+        }
+    }
+}
+
+impl<T: Clone + PartialEq + Unpin, U: Clone + PartialEq + Unpin> Signal for DerivedState<T, U> {
+    type Item = U;
+
     fn poll_change(
         self: Pin<&mut Self>,
         cx: &mut std::task::Context,
     ) -> std::task::Poll<Option<Self::Item>> {
-        let mut stream = self.stream.lock().unwrap();
-
-        match pin!(&mut *stream).poll_next_unpin(cx) {
-            std::task::Poll::Pending | std::task::Poll::Ready(None) => std::task::Poll::Pending,
-            std::task::Poll::Ready(Some(v)) => {
-                let mut inner = self.inner.write().unwrap();
-                if v == *inner {
-                    std::task::Poll::Pending
-                } else {
-                    *inner = v.clone();
-                    std::task::Poll::Ready(Some(v))
+        let this = self.get_mut();
+
+        loop {
+            match Pin::new(&mut this.source).poll_change(cx) {
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+                std::task::Poll::Ready(None) => return std::task::Poll::Ready(None),
+                std::task::Poll::Ready(Some(v)) => {
+                    let mapped = (this.map)(&v);
+                    if this.last.as_ref() != Some(&mapped) {
+                        this.last = Some(mapped.clone());
+                        return std::task::Poll::Ready(Some(mapped));
+                    }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::task::Poll;
+
+    use futures::executor::block_on;
+    use futures_signals::signal::SignalExt;
+
+    /// Poll `signal` once, without waiting for a change.
+    fn poll<S: Signal + Unpin>(signal: &mut S) -> Poll<Option<S::Item>> {
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        Pin::new(signal).poll_change(&mut cx)
+    }
+
+    #[test]
+    fn test_signal_ends_with_source() {
+        let (source, state) = DeviceState::new(0);
+        let mut signal = state.clone();
+
+        source.set(1);
+        assert_eq!(poll(&mut signal), Poll::Ready(Some(1)));
+        assert_eq!(poll(&mut signal), Poll::Pending);
+
+        let clone = source.clone();
+        drop(source);
+        clone.set(2);
+        drop(clone);
+        assert_eq!(block_on(signal.to_stream().collect::<Vec<_>>()), [2]);
+        assert_eq!(state.get(), 2);
+    }
+
+    #[test]
+    fn test_duplicates_are_skipped() {
+        let (source, mut state) = DeviceState::new(0);
+
+        for (value, expected) in [
+            (0, Poll::Pending),
+            (1, Poll::Ready(Some(1))),
+            (1, Poll::Pending),
+            (2, Poll::Ready(Some(2))),
+            (1, Poll::Ready(Some(1))),
+        ] {
+            source.set(value);
+            assert_eq!(poll(&mut state), expected);
+        }
+    }
+
+    #[test]
+    fn test_clones_see_all_changes() {
+        let (source, mut a) = DeviceState::new(0);
+        let mut b = a.clone();
+
+        source.set(1);
+        assert_eq!(poll(&mut a), Poll::Ready(Some(1)));
+        source.set(2);
+        assert_eq!(poll(&mut b), Poll::Ready(Some(2)));
+        assert_eq!(poll(&mut a), Poll::Ready(Some(2)));
+        assert_eq!(poll(&mut b), Poll::Pending);
+        assert_eq!(a.get(), 2);
+    }
+
+    #[test]
+    fn test_get_has_no_side_effects() {
+        let (source, state) = DeviceState::new(0);
+        let mut clone = state.clone();
+
+        source.set(1);
+        source.set(2);
+        assert_eq!(state.get(), 2);
+        assert_eq!(clone.get(), 2);
+
+        // reading the value doesn't take the change away from the signals
+        assert_eq!(poll(&mut clone), Poll::Ready(Some(2)));
+        assert_eq!(poll(&mut clone), Poll::Pending);
+        source.set(3);
+        assert_eq!(poll(&mut clone), Poll::Ready(Some(3)));
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        const LAST: u32 = 1000;
+
+        let (source, state) = DeviceState::new(0);
+
+        std::thread::scope(|s| {
+            // control loops reading the state as fast as they can
+            for _ in 0..4 {
+                let state = state.clone();
+                s.spawn(move || {
+                    let mut last = 0;
+                    while last < LAST {
+                        let value = state.get();
+                        assert!(value >= last);
+                        last = value;
+                    }
+                });
+            }
+            // a UI following the changes, which may skip some of them
+            let follower = state.clone();
+            s.spawn(move || {
+                let changes = follower
+                    .to_stream()
+                    .take_while(|value| std::future::ready(*value < LAST));
+                block_on(changes.fold(0, |last, value| {
+                    assert!(value > last);
+                    std::future::ready(value)
+                }));
+            });
+
+            s.spawn(move || {
+                for value in 1..=LAST {
+                    source.set(value);
+                }
+            });
+        });
+
+        assert_eq!(state.get(), LAST);
+    }
+
+    #[tokio::test]
+    async fn test_spawn() {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        let state = DeviceState::spawn(receiver, 0);
+        let mut changes = state.clone().to_stream();
+
+        sender.unbounded_send(1).unwrap();
+        assert_eq!(changes.next().await, Some(1));
+        assert_eq!(state.get(), 1);
+
+        // the state keeps being fed while nobody polls its signals
+        sender.unbounded_send(2).unwrap();
+        sender.unbounded_send(3).unwrap();
+        while state.get() != 3 {
+            tokio::task::yield_now().await;
+        }
+
+        drop(sender);
+        assert_eq!(changes.collect::<Vec<_>>().await, [3]);
+    }
+
+    #[test]
+    fn test_spawned_task_is_aborted() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (sender, receiver) = futures::channel::mpsc::unbounded::<u32>();
+        let state = runtime.block_on(async { DeviceState::spawn(receiver, 0) });
+        let clone = state.clone();
+
+        drop(state);
+        runtime.block_on(tokio::task::yield_now());
+        assert!(!sender.is_closed());
+
+        // the task drops the stream once the last clone is gone
+        drop(clone);
+        runtime.block_on(tokio::task::yield_now());
+        assert!(sender.is_closed());
+    }
+
+    #[test]
+    fn test_signal_cloned_starts_with_current_value() {
+        let (source, state) = DeviceState::new((0, 0));
+
+        source.set((1, 0));
+        let mut clone = state.clone();
+        let mut fresh = state.signal_cloned();
+        let mut mapped = state.derive(|state| state.1).signal_cloned();
+
+        // the clone only sees later changes, the other signals start with the current value
+        assert_eq!(poll(&mut clone), Poll::Pending);
+        assert_eq!(poll(&mut fresh), Poll::Ready(Some((1, 0))));
+        assert_eq!(poll(&mut mapped), Poll::Ready(Some(0)));
+
+        source.set((2, 0));
+        assert_eq!(poll(&mut fresh), Poll::Ready(Some((2, 0))));
+        assert_eq!(poll(&mut mapped), Poll::Pending);
+        source.set((2, 1));
+        drop(source);
+        assert_eq!(block_on(fresh.to_stream().collect::<Vec<_>>()), [(2, 1)]);
+        assert_eq!(block_on(mapped.to_stream().collect::<Vec<_>>()), [1]);
+        assert_eq!(state.get(), (2, 1));
+    }
+
+    #[test]
+    fn test_mapped_state() {
+        let (source, state) = DeviceState::new((0, 0));
+        let mut mapped = state.derive(|state| state.1);
+
+        let changes = [(1, 0), (1, 1), (2, 1), (2, 2), (3, 2)].map(|value| {
+            source.set(value);
+            poll(&mut mapped)
+        });
+
+        assert_eq!(
+            changes,
+            [
+                Poll::Pending,
+                Poll::Ready(Some(1)),
+                Poll::Pending,
+                Poll::Ready(Some(2)),
+                Poll::Pending
+            ]
+        );
+        assert_eq!(mapped.get(), 2);
+        drop(source);
+        assert_eq!(poll(&mut mapped), Poll::Ready(None));
+    }
+}
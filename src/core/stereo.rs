@@ -1,5 +1,6 @@
 /// A pair of two values that represent an audio channel each.
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stereo<T> {
     /// The value for Channel A.
     pub a: T,
@@ -7,6 +8,16 @@ pub struct Stereo<T> {
     pub b: T,
 }
 
+/// One of the two channels of a device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Channel {
+    /// Channel A.
+    A,
+    /// Channel B.
+    B,
+}
+
 impl<T> Stereo<T> {
     /// Create a value pair where both values are the same.
     pub fn symmetric(v: T) -> Self
@@ -15,6 +26,185 @@ impl<T> Stereo<T> {
     {
         Self { a: v.clone(), b: v }
     }
+    /// Apply `f` to the values of both channels.
+    ///
+    /// ```
+    /// # use dungeonctl::Stereo;
+    /// let limit = Stereo { a: 70, b: 30 };
+    ///
+    /// assert_eq!(limit.map(|v: u8| v.min(50)), Stereo { a: 50, b: 30 });
+    /// ```
+    pub fn map<U>(self, mut f: impl FnMut(T) -> U) -> Stereo<U> {
+        Stereo {
+            a: f(self.a),
+            b: f(self.b),
+        }
+    }
+    /// Combine the values of both channels with the values of `other` into pairs.
+    ///
+    /// ```
+    /// # use dungeonctl::Stereo;
+    /// let intensity = Stereo { a: 20, b: 40 };
+    /// let limit = Stereo { a: 70, b: 30 };
+    ///
+    /// assert_eq!(intensity.zip(limit), Stereo { a: (20, 70), b: (40, 30) });
+    /// ```
+    pub fn zip<U>(self, other: Stereo<U>) -> Stereo<(T, U)> {
+        Stereo {
+            a: (self.a, other.a),
+            b: (self.b, other.b),
+        }
+    }
+    /// Combine the values of both channels with the values of `other` using `f`.
+    ///
+    /// ```
+    /// # use dungeonctl::Stereo;
+    /// let first = Stereo { a: 20u8, b: 80 };
+    /// let second = Stereo { a: 60u8, b: 40 };
+    ///
+    /// let mixed = first.zip_with(second, |x, y| ((x as u16 + y as u16) / 2) as u8);
+    /// assert_eq!(mixed, Stereo { a: 40, b: 60 });
+    /// ```
+    pub fn zip_with<U, V>(self, other: Stereo<U>, mut f: impl FnMut(T, U) -> V) -> Stereo<V> {
+        Stereo {
+            a: f(self.a, other.a),
+            b: f(self.b, other.b),
+        }
+    }
+    /// Get a reference to the value of `channel`.
+    ///
+    /// ```
+    /// # use dungeonctl::{Channel, Stereo};
+    /// let mut intensity = Stereo { a: 20, b: 40 };
+    ///
+    /// assert_eq!(*intensity.get(Channel::B), 40);
+    ///
+    /// *intensity.get_mut(Channel::A) += 5;
+    /// assert_eq!(intensity, Stereo { a: 25, b: 40 });
+    /// ```
+    pub fn get(&self, channel: Channel) -> &T {
+        match channel {
+            Channel::A => &self.a,
+            Channel::B => &self.b,
+        }
+    }
+    /// Get a mutable reference to the value of `channel`.
+    pub fn get_mut(&mut self, channel: Channel) -> &mut T {
+        match channel {
+            Channel::A => &mut self.a,
+            Channel::B => &mut self.b,
+        }
+    }
+    /// Get references to the values of Channel A and B as an array.
+    pub fn as_array(&self) -> [&T; 2] {
+        [&self.a, &self.b]
+    }
+    /// Convert into an array of the values of Channel A and B.
+    pub fn into_array(self) -> [T; 2] {
+        [self.a, self.b]
+    }
+}
+
+/// Iterates over the values of Channel A and B, in that order.
+///
+/// ```
+/// # use dungeonctl::Stereo;
+/// let intensity = Stereo { a: 20, b: 40 };
+///
+/// for (channel, value) in ["A", "B"].into_iter().zip(intensity) {
+///     println!("Channel {channel}: {value}");
+/// }
+/// ```
+impl<T> IntoIterator for Stereo<T> {
+    type Item = T;
+    type IntoIter = std::array::IntoIter<T, 2>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_array().into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Stereo<T> {
+    type Item = &'a T;
+    type IntoIter = std::array::IntoIter<&'a T, 2>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_array().into_iter()
+    }
+}
+
+impl<T: Copy> Stereo<T> {
+    /// Exchange the values of Channel A and B, e.g. to correct for swapped electrodes.
+    ///
+    /// ```
+    /// # use dungeonctl::Stereo;
+    /// const SWAPPED: Stereo<u8> = Stereo { a: 20, b: 40 }.swap();
+    ///
+    /// assert_eq!(SWAPPED, Stereo { a: 40, b: 20 });
+    /// ```
+    pub const fn swap(self) -> Self {
+        Self {
+            a: self.b,
+            b: self.a,
+        }
+    }
+}
+
+impl Stereo<u8> {
+    /// Add the values of `rhs` per channel, saturating at 255 instead of overflowing.
+    pub fn saturating_add(self, rhs: Self) -> Self {
+        self.zip_with(rhs, u8::saturating_add)
+    }
+    /// Subtract the values of `rhs` per channel, saturating at 0 instead of overflowing.
+    pub fn saturating_sub(self, rhs: Self) -> Self {
+        self.zip_with(rhs, u8::saturating_sub)
+    }
+}
+
+/// Adds the values per channel.
+///
+/// This overflows just like `T` does, e.g. for `u8` it panics in debug builds and wraps around in
+/// release builds. Use [`Stereo::saturating_add()`] to avoid that.
+impl<T: std::ops::Add<Output = T>> std::ops::Add for Stereo<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self.zip_with(rhs, T::add)
+    }
+}
+
+/// Subtracts the values per channel.
+///
+/// This overflows just like `T` does, e.g. for `u8` it panics in debug builds and wraps around in
+/// release builds. Use [`Stereo::saturating_sub()`] to avoid that.
+impl<T: std::ops::Sub<Output = T>> std::ops::Sub for Stereo<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.zip_with(rhs, T::sub)
+    }
+}
+
+/// Multiplies the values per channel.
+///
+/// This overflows just like `T` does.
+impl<T: std::ops::Mul<Output = T>> std::ops::Mul for Stereo<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.zip_with(rhs, T::mul)
+    }
+}
+
+/// Multiplies the values of both channels with a scalar.
+///
+/// This overflows just like `T` does.
+impl<T: std::ops::Mul<Output = T> + Clone> std::ops::Mul<T> for Stereo<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: T) -> Self::Output {
+        self * Self::symmetric(rhs)
+    }
 }
 
 impl<T> From<(T, T)> for Stereo<T> {
@@ -59,3 +249,24 @@ where
         (self.a, self.b).write_options(writer, endian, args)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arithmetic() {
+        let x = Stereo { a: 20u8, b: 100 };
+        let y = Stereo { a: 10u8, b: 2 };
+
+        assert_eq!(x + y, Stereo { a: 30, b: 102 });
+        assert_eq!(x - y, Stereo { a: 10, b: 98 });
+        assert_eq!(x * y, Stereo { a: 200, b: 200 });
+        assert_eq!(x * 2, Stereo { a: 40, b: 200 });
+        assert_eq!(
+            Stereo { a: 200u8, b: 100 }.saturating_add(Stereo { a: 100, b: 100 }),
+            Stereo { a: 255, b: 200 }
+        );
+        assert_eq!(y.saturating_sub(x), Stereo { a: 0, b: 0 });
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures::future::BoxFuture;
+
+/// A clock for all timing of this library, like sending pulses every 100 ms, timeouts and
+/// reconnection delays.
+///
+/// [`TokioTimer`] is used by default. To use another clock, e.g. to control the time in tests or
+/// to take it from another async runtime, implement this trait and pass it to e.g.
+/// [`Coyote3Builder::timer()`](crate::coyote3::Coyote3Builder::timer). Only the timing is
+/// abstracted: Tokio stays a required dependency, and connecting still needs a Tokio runtime to
+/// spawn tasks onto, see the [crate docs](crate#runtime).
+///
+/// ```ignore
+/// #[derive(Debug)]
+/// struct AsyncStdTimer;
+///
+/// impl Timer for AsyncStdTimer {
+///     fn now(&self) -> Instant {
+///         Instant::now()
+///     }
+///     fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+///         Box::pin(async_std::task::sleep(deadline.saturating_duration_since(Instant::now())))
+///     }
+/// }
+/// ```
+pub trait Timer: std::fmt::Debug + Send + Sync + 'static {
+    /// The current time.
+    fn now(&self) -> Instant;
+    /// Wait until `deadline` has passed.
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+}
+
+/// A [`Timer`] backed by the Tokio time driver.
+///
+/// This must be used from within a Tokio runtime with the time driver enabled. It respects
+/// [paused time](https://docs.rs/tokio/latest/tokio/time/fn.pause.html), so it can be used in
+/// tests.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioTimer;
+
+impl Timer for TokioTimer {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline.into()))
+    }
+}
+
+/// Wait for `duration` using `timer`.
+pub(crate) async fn sleep(timer: &dyn Timer, duration: Duration) {
+    timer.sleep_until(timer.now() + duration).await;
+}
+
+/// Run `future` until it completes or `duration` has passed according to `timer`, returning
+/// `None` in the latter case.
+pub(crate) async fn timeout<F: Future>(
+    timer: &dyn Timer,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    let deadline = timer.sleep_until(timer.now() + duration);
+
+    match futures::future::select(std::pin::pin!(future), deadline).await {
+        futures::future::Either::Left((output, _)) => Some(output),
+        futures::future::Either::Right(((), _)) => None,
+    }
+}
+
+/// Ticks every `period` using a [`Timer`], starting immediately.
+///
+/// If a tick is missed, the following ticks are delayed instead of catching up, like Tokio's
+/// [`MissedTickBehavior::Delay`](tokio::time::MissedTickBehavior::Delay).
+#[derive(Debug)]
+pub(crate) struct Interval {
+    timer: Arc<dyn Timer>,
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl Interval {
+    pub(crate) fn new(timer: Arc<dyn Timer>, period: Duration) -> Self {
+        Self {
+            timer,
+            period,
+            next: None,
+        }
+    }
+    /// Wait for the next tick.
+    pub(crate) async fn tick(&mut self) {
+        let now = self.timer.now();
+        let deadline = self.next.unwrap_or(now);
+
+        if deadline > now {
+            self.timer.sleep_until(deadline).await;
+        }
+
+        self.next = Some(deadline.max(now) + self.period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use futures::executor::block_on;
+
+    /// A clock that only advances when sleeping or when advanced explicitly.
+    #[derive(Debug)]
+    struct ManualTimer {
+        now: Mutex<Instant>,
+    }
+
+    impl ManualTimer {
+        fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+    }
+
+    impl Timer for ManualTimer {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+        fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+            let mut now = self.now.lock().unwrap();
+            *now = (*now).max(deadline);
+            Box::pin(std::future::ready(()))
+        }
+    }
+
+    #[test]
+    fn test_interval() {
+        let start = Instant::now();
+        let timer = Arc::new(ManualTimer {
+            now: Mutex::new(start),
+        });
+        let mut interval = Interval::new(timer.clone(), Duration::from_millis(100));
+        let mut tick = |work: u64| {
+            timer.advance(Duration::from_millis(work));
+            block_on(interval.tick());
+            timer.now().duration_since(start).as_millis()
+        };
+
+        assert_eq!(tick(0), 0);
+        assert_eq!(tick(30), 100);
+        assert_eq!(tick(0), 200);
+        // the tick at 300 ms is missed, so the following ones are delayed
+        assert_eq!(tick(150), 350);
+        assert_eq!(tick(10), 450);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = Instant::now();
+        let timer = ManualTimer {
+            now: Mutex::new(start),
+        };
+
+        assert_eq!(
+            block_on(timeout(&timer, Duration::from_secs(1), async { 1 })),
+            Some(1)
+        );
+
+        // the manual timer jumps to a deadline as soon as it is set, even if the future wins
+        let never = std::future::pending::<()>();
+        assert_eq!(
+            block_on(timeout(&timer, Duration::from_secs(1), never)),
+            None
+        );
+        assert_eq!(timer.now(), start + Duration::from_secs(2));
+    }
+}
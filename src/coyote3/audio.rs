@@ -0,0 +1,138 @@
+use crate::Stereo;
+
+use super::{Amplitude, Frequency, Pulses, frames};
+
+/// The duration of a single pulse in seconds, which is the length of each bucket of samples.
+const BUCKET_SECONDS: f64 = 0.025;
+
+/// How the frequency of the pulses is chosen by [`from_audio()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFrequency {
+    /// Use the same frequency for all pulses.
+    Fixed(Frequency),
+    /// Estimate the frequency of each bucket from its zero crossings, clamped to 1–200 Hz.
+    ///
+    /// A bucket only spans 25 ms, so the estimate is coarse: it can only take multiples of
+    /// 20 Hz. Buckets without any crossing get the lowest frequency.
+    ZeroCrossings,
+}
+
+/// Convert normalized audio `samples` in the range of -1.0 to 1.0, taken at `sample_rate` Hz,
+/// into [`Pulses`] frames following the envelope of the audio.
+///
+/// The samples are downsampled into buckets of 25 ms, one for each pulse, so each frame covers
+/// 100 ms of audio. The amplitude of a pulse is the peak of the absolute sample values within
+/// its bucket, scaled to 0–100. The last bucket may be shorter and the last frame is padded
+/// with silent pulses, see [`frames()`].
+///
+/// Both channels play the same pulses and the intensity is not changed.
+///
+/// ```
+/// # use dungeonctl::coyote3::{AudioFrequency, Frequency, from_audio};
+/// // one second of a 440 Hz tone at half volume
+/// let samples = (0..44_100)
+///     .map(|i| 0.5 * (std::f32::consts::TAU * 440.0 * i as f32 / 44_100.0).sin())
+///     .collect::<Vec<_>>();
+///
+/// let frames = from_audio(&samples, 44_100, AudioFrequency::Fixed(Frequency::hz(100)?));
+/// assert_eq!(frames.count(), 10);
+/// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+/// ```
+pub fn from_audio(
+    samples: &[f32],
+    sample_rate: u32,
+    frequency: AudioFrequency,
+) -> impl Iterator<Item = Pulses> + use<'_> {
+    let bucket_len = ((sample_rate as f64 * BUCKET_SECONDS).round() as usize).max(1);
+
+    let pulses = samples
+        .chunks(bucket_len)
+        .enumerate()
+        .map(move |(index, bucket)| {
+            let peak = bucket.iter().fold(0.0f32, |peak, x| peak.max(x.abs()));
+            let amplitude = Amplitude::from((peak.min(1.0) * 100.0).round() as u8);
+
+            let frequency = match frequency {
+                AudioFrequency::Fixed(frequency) => frequency,
+                AudioFrequency::ZeroCrossings => {
+                    // include the last sample of the previous bucket to not miss a crossing in between
+                    let start = index * bucket_len;
+                    let crossings = samples[start.saturating_sub(1)..start + bucket.len()]
+                        .windows(2)
+                        .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+                        .count();
+                    let hz = crossings as f64 / 2.0 / BUCKET_SECONDS;
+                    Frequency::hz(hz.round().clamp(1.0, 200.0) as u8)
+                        .expect("the frequency is clamped to the valid range")
+                }
+            };
+
+            (frequency, amplitude)
+        });
+
+    frames(pulses, Stereo::symmetric(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coyote3::Pulse;
+
+    fn sine(hz: f32, volume: f32, seconds: f32, sample_rate: u32) -> Vec<f32> {
+        let len = (seconds * sample_rate as f32) as usize;
+        (0..len)
+            .map(|i| volume * (std::f32::consts::TAU * hz * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_from_audio() {
+        // 400 Hz reaches its peak within each 25 ms bucket of 200 samples
+        let samples = [0.5, 0.5, 0.5, 0.5, 1.0, 0.25]
+            .into_iter()
+            .flat_map(|volume| sine(400.0, volume, 0.025, 8000))
+            .collect::<Vec<_>>();
+
+        let frames = from_audio(
+            &samples,
+            8000,
+            AudioFrequency::Fixed(Frequency::hz(100).unwrap()),
+        )
+        .collect::<Vec<_>>();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].pulses.map(|p| p.a.intensity.value()), [50; 4]);
+        assert_eq!(
+            frames[1].pulses.map(|p| p.a.intensity.value()),
+            [100, 25, 0, 0]
+        );
+        assert_eq!(frames[1].pulses[1].a.frequency, Frequency::hz(100).unwrap());
+        // the padding is silent
+        assert_eq!(frames[1].pulses[3].a, Pulse::silent());
+        assert!(frames.iter().flat_map(|f| f.pulses).all(|p| p.a == p.b));
+        assert_eq!(frames[1].intensity, Pulses::silent().intensity);
+    }
+
+    #[test]
+    fn test_zero_crossings() {
+        let frequencies = |hz: f32| {
+            let samples = sine(hz, 1.0, 0.1, 48_000);
+            from_audio(&samples, 48_000, AudioFrequency::ZeroCrossings)
+                .flat_map(|f| f.pulses)
+                .map(|p| p.a.frequency)
+                .collect::<Vec<_>>()
+        };
+        let hz = |hz| [Frequency::hz(hz).unwrap(); 4];
+
+        // two or three crossings per bucket, depending on where they fall
+        for frequency in frequencies(50.0) {
+            assert!(
+                [40, 60]
+                    .map(|hz| Frequency::hz(hz).unwrap())
+                    .contains(&frequency)
+            );
+        }
+        assert_eq!(frequencies(1000.0), hz(200));
+        assert_eq!(frequencies(0.0), hz(1));
+    }
+}
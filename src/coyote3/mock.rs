@@ -0,0 +1,244 @@
+use std::sync::{Arc, Mutex};
+
+use super::{DeviceSettings, Pulses, State, check_settings};
+use crate::{
+    Device, Result,
+    core::{DeviceState, StateSignal, StateSource},
+};
+
+/// An in-memory stand-in for a [`Coyote3`](super::Coyote3) to test control logic without a
+/// device.
+///
+/// It records all commands that are sent to it and applies them to its state like a device
+/// would: intensity changes are applied within the [limit](DeviceSettings::limit) and settings
+/// are taken over. Use [`update_state()`](Self::update_state) to simulate anything the device
+/// reports on its own, like the battery draining or the “shoulder” switches being used.
+///
+/// ```
+/// # use dungeonctl::{Device, Stereo, StateSignal, coyote3::{IntensityChange, MockCoyote3, Pulses, SentCommand}};
+/// # futures::executor::block_on(async {
+/// let coyote = MockCoyote3::new();
+/// coyote
+///     .send_pulses(Pulses {
+///         intensity: Stereo::symmetric(IntensityChange::AbsoluteChange(20)),
+///         ..Pulses::silent()
+///     })
+///     .await?;
+///
+/// assert_eq!(coyote.state().get().intensity, Stereo { a: 20, b: 20 });
+/// assert!(matches!(coyote.sent()[..], [SentCommand::Pulses(_)]));
+/// # dungeonctl::Result::Ok(())
+/// # }).unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct MockCoyote3 {
+    sent: Arc<Mutex<Vec<SentCommand>>>,
+    current: Arc<Mutex<State>>,
+    source: StateSource<State>,
+    state: DeviceState<State>,
+}
+
+/// A command that was sent to a [`MockCoyote3`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum SentCommand {
+    /// The pulses sent using [`Device::send_pulses()`] or [`Device::stop()`].
+    Pulses(Pulses),
+    /// The settings sent using [`Device::update_settings()`].
+    Settings(DeviceSettings),
+}
+
+impl Default for MockCoyote3 {
+    fn default() -> Self {
+        Self::with_state(State {
+            battery: 100,
+            connected: true,
+            ..Default::default()
+        })
+    }
+}
+
+impl MockCoyote3 {
+    /// Create a connected mock with a full battery, zero intensity and the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Create a mock with the given initial state.
+    pub fn with_state(state: State) -> Self {
+        let (source, tracked) = DeviceState::new(state);
+
+        Self {
+            sent: Default::default(),
+            current: Arc::new(Mutex::new(state)),
+            source,
+            state: tracked,
+        }
+    }
+    /// Get all commands that were sent so far, oldest first.
+    pub fn sent(&self) -> Vec<SentCommand> {
+        self.sent.lock().unwrap().clone()
+    }
+    /// Forget the commands that were sent so far.
+    pub fn clear_sent(&self) {
+        self.sent.lock().unwrap().clear();
+    }
+    /// Change the state as if the device had reported a change.
+    pub fn update_state(&self, update: impl FnOnce(&mut State)) {
+        let mut current = self.current.lock().unwrap();
+        update(&mut current);
+        self.source.set(*current);
+    }
+    fn record(&self, command: SentCommand) {
+        self.sent.lock().unwrap().push(command);
+    }
+}
+
+/// Like [`Coyote3`](super::Coyote3), mocks are equal if they stand in for the same device:
+/// clones compare equal, while separately created mocks don't.
+impl PartialEq for MockCoyote3 {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.current, &other.current)
+    }
+}
+
+impl Eq for MockCoyote3 {}
+
+impl std::hash::Hash for MockCoyote3 {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.current).hash(state);
+    }
+}
+
+impl crate::core::Sealed for MockCoyote3 {}
+
+impl Device for MockCoyote3 {
+    type State = State;
+    type Pulses = Pulses;
+    type Settings = DeviceSettings;
+
+    fn state(&self) -> impl StateSignal<State> + Send + use<> {
+        self.state.clone()
+    }
+    async fn send_pulses(&self, pulses: Pulses) -> Result<()> {
+        self.record(SentCommand::Pulses(pulses));
+        self.update_state(|state| {
+            state.intensity = state
+                .intensity
+                .zip(pulses.intensity)
+                .zip_with(state.settings.limit, |(current, change), limit| {
+                    change.applied_to(current, limit)
+                });
+        });
+
+        Ok(())
+    }
+    async fn update_settings(&self, settings: DeviceSettings) -> Result<()> {
+        check_settings(&settings, None)?;
+        self.record(SentCommand::Settings(settings));
+        self.update_state(|state| state.settings = settings);
+
+        Ok(())
+    }
+    async fn stop(&self) -> Result<()> {
+        self.send_pulses(Pulses::STOP).await
+    }
+    async fn disconnect(&self) -> Result<()> {
+        self.stop().await?;
+        self.update_state(|state| state.connected = false);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{StreamExt, executor::block_on};
+    use futures_signals::signal::SignalExt;
+
+    use super::super::IntensityChange;
+    use crate::Stereo;
+
+    #[test]
+    fn test_mock_records_commands() {
+        let coyote = MockCoyote3::new();
+        let settings = DeviceSettings {
+            limit: Stereo { a: 30, b: 100 },
+            ..Default::default()
+        };
+        let pulses = Pulses {
+            intensity: Stereo {
+                a: IntensityChange::AbsoluteChange(50),
+                b: IntensityChange::RelativeIncrease(10),
+            },
+            ..Pulses::silent()
+        };
+
+        block_on(async {
+            coyote.update_settings(settings).await.unwrap();
+            coyote.send_pulses(pulses).await.unwrap();
+        });
+
+        assert_eq!(
+            coyote.sent(),
+            [SentCommand::Settings(settings), SentCommand::Pulses(pulses)]
+        );
+        assert_eq!(coyote.state().get().intensity, Stereo { a: 30, b: 10 });
+
+        coyote.clear_sent();
+        assert_eq!(coyote.sent(), []);
+    }
+
+    #[test]
+    fn test_mock_state_updates() {
+        let coyote = MockCoyote3::new();
+        let battery = coyote.state().map(|state| state.battery).to_stream();
+
+        coyote.update_state(|state| state.battery = 90);
+        coyote.update_state(|state| state.battery = 80);
+        block_on(coyote.disconnect()).unwrap();
+
+        assert_eq!(coyote.state().get().battery, 80);
+        assert!(!coyote.state().get().connected);
+        drop(coyote);
+        // like any signal, it skips the values in between if it isn't polled
+        assert_eq!(block_on(battery.collect::<Vec<_>>()), [80]);
+    }
+
+    #[tokio::test]
+    async fn test_guard_stops_output() {
+        let coyote = MockCoyote3::new();
+
+        let guard = coyote.guard();
+        guard.send_pulses(Pulses::silent()).await.unwrap();
+        drop(guard);
+        tokio::task::yield_now().await;
+
+        assert!(matches!(
+            coyote.sent()[..],
+            [SentCommand::Pulses(_), SentCommand::Pulses(Pulses::STOP)]
+        ));
+
+        coyote.clear_sent();
+        coyote.guard().disarm();
+        tokio::task::yield_now().await;
+
+        assert!(coyote.sent().is_empty());
+    }
+
+    #[test]
+    fn test_identity() {
+        let coyote = MockCoyote3::new();
+        let clone = coyote.clone();
+        let other = MockCoyote3::new();
+
+        assert_eq!(coyote, clone);
+        assert_ne!(coyote, other);
+
+        // the hash only depends on the identity, not on the mutable state
+        #[allow(clippy::mutable_key_type)]
+        let devices = std::collections::HashSet::from([coyote, clone, other]);
+        assert_eq!(devices.len(), 2);
+    }
+}
@@ -0,0 +1,5812 @@
+//! Implemention of the Bluetooth LE protocols to control the DG-LAB Coyote 3.
+
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use arrayvec::ArrayVec;
+use binrw::BinRead;
+use btleplug::{
+    api::{
+        BDAddr, Central, CentralEvent, Characteristic, Manager as _, Peripheral as _,
+        PeripheralProperties, ScanFilter, ValueNotification, WriteType,
+    },
+    platform::{Adapter, Manager, Peripheral, PeripheralId},
+};
+use futures::{FutureExt, Stream, StreamExt, future::BoxFuture};
+use smart_default::SmartDefault;
+use tracing::{Instrument, Span, debug, error, info_span, warn};
+use uuid::{Uuid, uuid};
+
+use crate::{
+    Channel, Device, Error, Result,
+    core::{
+        CentralExt, DeviceState, Interval, PeripheralExt, PeripheralFilter, StateSignal, Stereo,
+        Timer, TokioTimer, sleep, timeout,
+    },
+};
+
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(feature = "mock")]
+mod mock;
+mod persistent;
+mod recording;
+#[cfg(feature = "relay")]
+mod relay;
+mod waveform;
+mod writer;
+
+#[cfg(feature = "audio")]
+pub use self::audio::{AudioFrequency, from_audio};
+#[cfg(feature = "mock")]
+pub use self::mock::{MockCoyote3, SentCommand};
+pub use self::persistent::ConnectionEvent;
+#[cfg(feature = "relay")]
+pub use self::relay::relay;
+pub use self::waveform::{
+    Envelope, Pattern, PulsesBuilder, Sequencer, WaveformBuilder, frames, interpolate,
+};
+use self::{
+    recording::{Recorder, parse_recording, play_recording},
+    writer::{DryRunTransport, PeripheralTransport, Writer},
+};
+
+const DEVICE_NAME: &str = "47L121000";
+// const BATTERY_SERVICE_UUID: Uuid = uuid!("0000180A-0000-1000-8000-00805f9b34fb");
+const MAIN_SERVICE_UUID: Uuid = uuid!("0000180C-0000-1000-8000-00805f9b34fb");
+const WRITE_CHARACTERISTIC_UUID: Uuid = uuid!("0000150A-0000-1000-8000-00805f9b34fb");
+const NOTIFY_CHARACTERISTIC_UUID: Uuid = uuid!("0000150B-0000-1000-8000-00805f9b34fb");
+const BATTERY_CHARACTERISTIC_UUID: Uuid = uuid!("00001500-0000-1000-8000-00805f9b34fb");
+const MODEL_NUMBER_CHARACTERISTIC_UUID: Uuid = uuid!("00002A24-0000-1000-8000-00805f9b34fb");
+const FIRMWARE_REVISION_CHARACTERISTIC_UUID: Uuid = uuid!("00002A26-0000-1000-8000-00805f9b34fb");
+const HARDWARE_REVISION_CHARACTERISTIC_UUID: Uuid = uuid!("00002A27-0000-1000-8000-00805f9b34fb");
+const VOLTAGE_CHARACTERISTIC_UUID: Uuid = uuid!("00002B18-0000-1000-8000-00805f9b34fb");
+const PULSE_INTERVAL: Duration = Duration::from_millis(100);
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(1);
+/// The number of intervals between sent pulses that [`Coyote3::jitter()`] is calculated over.
+const JITTER_WINDOW: usize = 10;
+/// The number of commands that may wait to be written by default, see
+/// [`Coyote3Builder::write_queue_depth()`].
+const WRITE_QUEUE_DEPTH: usize = 4;
+
+/// Implements the Bluetooth LE protocols to control the DG-LAB Coyote 3.
+///
+/// Based on <https://github.com/DG-LAB-OPENSOURCE/DG-LAB-OPENSOURCE/blob/main/coyote/v3/README_V3.md> (Chinese).
+///
+/// # Multiple devices
+///
+/// Several devices can be controlled at once, even through the same adapter. Each `Coyote3` only
+/// tracks the state of its own device. Connections can be made concurrently: devices that are
+/// connected already are skipped while scanning, so each connection picks a different device.
+/// To tell the devices apart, connect to specific ones with
+/// [`address()`](Coyote3Builder::address) or [`scan()`](Self::scan) for them first:
+///
+/// ```ignore
+/// let devices = Coyote3::scan(&adapter, Duration::from_secs(5)).await?;
+/// let [first, second] = &devices[..] else {
+///     return Err(Error::DeviceNotFound);
+/// };
+///
+/// let (first, second) = tokio::try_join!(
+///     Coyote3::connect().with(adapter.clone()).to(first.peripheral.clone()),
+///     Coyote3::connect().with(adapter).to(second.peripheral.clone()),
+/// )?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct Coyote3 {
+    peripheral: Peripheral,
+    battery: Characteristic,
+    voltage: Option<Characteristic>,
+    writer: Writer,
+    state: DeviceState<State>,
+    settings: Arc<Mutex<DeviceSettings>>,
+    reconnect: Option<Arc<ReconnectTask>>,
+    soft_limit: Option<Stereo<u8>>,
+    limit_check: Option<LimitCheck>,
+    safety_threshold: Option<u8>,
+    device_info: Arc<DeviceInfo>,
+    enabled: Arc<Mutex<Stereo<bool>>>,
+    settings_write_type: WriteType,
+    pulse_interval: Duration,
+    timer: Arc<dyn Timer>,
+    swap_channels: bool,
+    projection: Arc<Mutex<Projection>>,
+    serials: Arc<Mutex<Serials>>,
+    emergency: Arc<Mutex<EmergencyStop>>,
+    pause: Arc<Mutex<Pause>>,
+    send_times: Arc<Mutex<SendTimes>>,
+    /// Where the pulses sent are recorded to, see [`Coyote3::record()`].
+    recorder: Arc<Mutex<Option<Recorder<std::io::BufWriter<std::fs::File>>>>>,
+    /// Reports the changes the commands would have made in a dry run, see
+    /// [`Coyote3Builder::dry_run()`].
+    dry_run: Option<futures::channel::mpsc::UnboundedSender<Update>>,
+    /// The span carrying the address of the device, to attribute its logs to it.
+    span: Span,
+}
+impl Coyote3 {
+    /// Connect to a Coyote 3.
+    ///
+    /// # Examples
+    ///
+    /// Connect to the first Coyote 3 that could be found using the first BLE adapter that could be found.
+    ///
+    /// ```no_run
+    /// # use dungeonctl::Coyote3;
+    /// # #[tokio::main]
+    /// # async fn main() -> eyre::Result<()> {
+    /// Coyote3::connect().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Connect to a specific Coyote 3 device using a specific BLE adapter and specific settings.
+    ///
+    /// ```ignore
+    /// Coyote3::connect()
+    ///     // `adapter` must be a `btleplug::platform::Adapter`
+    ///     .with(adapter)
+    ///     // `peripheral` must be a `btleplug::platform::Peripheral`
+    ///     .to(peripheral)
+    ///     .settings(DeviceSettings {
+    ///         limit: Stereo { a: 50, b: 0 },
+    ///         ..Default::default()
+    ///     })
+    ///     .await?;
+    /// ```
+    pub fn connect() -> Coyote3Builder {
+        Coyote3Builder::default()
+    }
+    /// Connect to a Coyote 3 with a low [limit](DeviceSettings::limit) of
+    /// [`SAFE_LIMIT`](DeviceSettings::SAFE_LIMIT) on both channels.
+    ///
+    /// This is the recommended entry point when getting started: the default limit of 70 can
+    /// already be painful, especially with new electrodes or placements. The limit has to be
+    /// raised explicitly using [`update_settings()`](Self::update_settings) or by passing other
+    /// [`settings()`](Coyote3Builder::settings) to the builder, and a warning is logged whenever
+    /// it is raised above the safe limit (see [`Coyote3Builder::safety_threshold()`]).
+    ///
+    /// ```no_run
+    /// # use dungeonctl::Coyote3;
+    /// # #[tokio::main]
+    /// # async fn main() -> eyre::Result<()> {
+    /// let coyote = Coyote3::connect_safe().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn connect_safe() -> Coyote3Builder {
+        Coyote3::connect()
+            .settings(DeviceSettings::safe())
+            .safety_threshold(DeviceSettings::SAFE_LIMIT)
+    }
+    /// Connect to a Coyote 3 and keep reconnecting whenever the connection is lost, for
+    /// long-running services.
+    ///
+    /// Each connection is made using `builder`, reporting a new [`Coyote3`] with
+    /// [`ConnectionEvent::Connected`]. When connecting fails or the connection is lost, the next
+    /// attempt is made after the delay configured by `reconnect`. If
+    /// [`max_attempts`](AutoReconnect::max_attempts) reconnection attempts in a row fail, the
+    /// stream ends.
+    ///
+    /// On reconnect, the settings of the previous connection are applied again, including
+    /// changes made with [`update_settings()`](Self::update_settings). The
+    /// [`auto_reconnect()`](Coyote3Builder::auto_reconnect) option of `builder` is ignored, since
+    /// reconnecting is handled by the stream.
+    ///
+    /// ```no_run
+    /// # use dungeonctl::{Coyote3, coyote3::{AutoReconnect, ConnectionEvent}};
+    /// # use futures::StreamExt;
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let mut events = std::pin::pin!(Coyote3::connect_persistent(
+    ///     Coyote3::connect(),
+    ///     AutoReconnect::default(),
+    /// ));
+    ///
+    /// while let Some(event) = events.next().await {
+    ///     if let ConnectionEvent::Connected(coyote) = event {
+    ///         println!("connected to {:?}", coyote.device_info());
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub fn connect_persistent(
+        mut builder: Coyote3Builder,
+        reconnect: AutoReconnect,
+    ) -> impl Stream<Item = ConnectionEvent> + Send + use<> {
+        builder.auto_reconnect = None;
+        let timer = builder.effective_timer();
+
+        persistent::persistent(
+            move |last: Option<&Coyote3>| {
+                let mut builder = builder.clone();
+                if let Some(last) = last {
+                    builder.settings = last.settings();
+                }
+                builder.into_future()
+            },
+            reconnect,
+            timer,
+        )
+    }
+    /// Scan for nearby Coyote 3 devices for the given `duration`.
+    ///
+    /// Only devices advertising the main service of the Coyote 3 (0x180C) are scanned for. Any
+    /// of the returned devices can be passed to [`Coyote3Builder::to()`] to connect to it.
+    ///
+    /// ```ignore
+    /// let devices = Coyote3::scan(&adapter, Duration::from_secs(5)).await?;
+    /// // let the user pick one of `devices`
+    /// Coyote3::connect().with(adapter).to(device).await?;
+    /// ```
+    pub async fn scan(adapter: &Adapter, duration: Duration) -> Result<Vec<DiscoveredDevice>> {
+        Self::scan_with_timer(adapter, duration, &TokioTimer).await
+    }
+    /// Scan for nearby Coyote 3 devices like [`scan()`](Self::scan), waiting for `duration`
+    /// using `timer` instead of the Tokio time driver.
+    pub async fn scan_with_timer(
+        adapter: &Adapter,
+        duration: Duration,
+        timer: &dyn Timer,
+    ) -> Result<Vec<DiscoveredDevice>> {
+        let filter = PeripheralFilter {
+            local_name: DEVICE_NAME,
+            address: None,
+        };
+
+        let scan = adapter.start_shared_scan(default_scan_filter()).await?;
+        sleep(timer, duration).await;
+        scan.stop().await?;
+
+        let mut devices = Vec::new();
+
+        for peripheral in adapter.peripherals().await? {
+            let Some(properties) = peripheral.properties().await? else {
+                continue;
+            };
+
+            if filter.matches(&properties) {
+                devices.push(DiscoveredDevice {
+                    advertisement: Advertisement::from(&properties),
+                    address: properties.address,
+                    local_name: properties.local_name,
+                    rssi: properties.rssi,
+                    peripheral,
+                });
+            }
+        }
+
+        Ok(devices)
+    }
+    /// Disconnect from the Coyote3.
+    ///
+    /// This tries to [`stop()`](Self::stop) the output before disconnecting. If
+    /// [automatic reconnection](Coyote3Builder::auto_reconnect) is enabled, it is turned off.
+    pub async fn disconnect(&self) -> Result<()> {
+        if let Some(reconnect) = &self.reconnect {
+            reconnect.0.abort();
+        }
+
+        if let Err(e) = self.stop().await {
+            error!(?e, "failed to stop output before disconnecting");
+        }
+
+        self.peripheral.disconnect().await?;
+
+        Ok(())
+    }
+}
+
+/// A Coyote 3 that was found by [`Coyote3::scan()`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct DiscoveredDevice {
+    /// The Bluetooth address of the device.
+    pub address: BDAddr,
+    /// The advertised local name of the device.
+    pub local_name: Option<String>,
+    /// The signal strength in dBm, if reported by the platform.
+    pub rssi: Option<i16>,
+    /// The data advertised by the device during the scan.
+    pub advertisement: Advertisement,
+    /// The peripheral that can be passed to [`Coyote3Builder::to()`].
+    pub peripheral: Peripheral,
+}
+
+impl From<DiscoveredDevice> for Peripheral {
+    fn from(device: DiscoveredDevice) -> Self {
+        device.peripheral
+    }
+}
+
+/// The advertisement data of a [`DiscoveredDevice`].
+///
+/// This is collected from the `ServiceDataAdvertisement`, `ManufacturerDataAdvertisement` and
+/// `ServicesAdvertisement` events received while scanning, with later advertisements replacing
+/// earlier data per key. No interpretation of the data is done.
+///
+/// Which fields are populated depends on the platform and on what the device advertises:
+///
+/// - The services, service data and manufacturer data are reported on all platforms (Linux,
+///   Windows, macOS/iOS and Android), but are empty if the device doesn't advertise them.
+/// - macOS and iOS never report the data of services handled by CoreBluetooth itself.
+/// - The TX power level is only set if the device advertises it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Advertisement {
+    /// The advertised service UUIDs.
+    pub services: Vec<Uuid>,
+    /// The data advertised for a service, by service UUID.
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// The manufacturer-specific data, by company identifier.
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// The advertised transmission power level in dBm.
+    pub tx_power_level: Option<i16>,
+}
+
+impl From<&PeripheralProperties> for Advertisement {
+    fn from(properties: &PeripheralProperties) -> Self {
+        Self {
+            services: properties.services.clone(),
+            service_data: properties.service_data.clone(),
+            manufacturer_data: properties.manufacturer_data.clone(),
+            tx_power_level: properties.tx_power_level,
+        }
+    }
+}
+
+/// Builder type to connect to a Coyote 3.
+///
+/// This type implements [`IntoFuture`], so you just need to `.await` it to start the connection.
+/// This must be done from within a Tokio runtime.
+#[derive(Clone, Debug, Default)]
+pub struct Coyote3Builder {
+    adapter: AdapterChoice,
+    manager: Option<Manager>,
+    peripheral: Option<Peripheral>,
+    address: Option<BDAddr>,
+    device_name: Option<String>,
+    settings: DeviceSettings,
+    timeout: Option<Duration>,
+    auto_reconnect: Option<AutoReconnect>,
+    soft_limit: Option<Stereo<u8>>,
+    limit_check: Option<LimitCheck>,
+    safety_threshold: Option<u8>,
+    reliable_settings: bool,
+    swap_channels: bool,
+    dry_run: bool,
+    pulse_interval: Option<Duration>,
+    timer: Option<Arc<dyn Timer>>,
+    service_cache: Option<ServiceCache>,
+    scan_filter: Option<ScanFilter>,
+    scan_retries: u32,
+    on_progress: ProgressCallback,
+    write_queue_depth: Option<usize>,
+}
+
+impl Coyote3Builder {
+    /// Connect using a specific [`btleplug::platform::Adapter`].
+    ///
+    /// By default, the first adapter is used. If there is none, connecting fails with
+    /// [`Error::AdapterUnavailable`].
+    pub fn with(mut self, adapter: impl Into<Adapter>) -> Self {
+        self.adapter = AdapterChoice::Given(adapter.into());
+        self
+    }
+    /// Connect using the adapter at `index` in the list returned by
+    /// [`Manager::adapters()`](btleplug::api::Manager::adapters).
+    ///
+    /// If there is no such adapter, connecting fails with [`Error::AdapterUnavailable`].
+    pub fn adapter_index(mut self, index: usize) -> Self {
+        self.adapter = AdapterChoice::Index(index);
+        self
+    }
+    /// Connect using the adapter with the given name, e.g. `hci1` on Linux.
+    ///
+    /// An adapter matches if its [`adapter_info()`](btleplug::api::Central::adapter_info) is
+    /// `name` or starts with `name` followed by a space. If there is no such adapter, connecting
+    /// fails with [`Error::AdapterUnavailable`].
+    pub fn adapter_by_name(mut self, name: impl Into<String>) -> Self {
+        self.adapter = AdapterChoice::Name(name.into());
+        self
+    }
+    /// Pick the adapter out of the ones of `manager` instead of creating a new
+    /// [`Manager`].
+    ///
+    /// This is ignored if an adapter was given using [`with()`](Self::with).
+    pub fn with_manager(mut self, manager: Manager) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+    /// Get the adapter this builder connects with, picked as configured by [`with()`](Self::with),
+    /// [`adapter_index()`](Self::adapter_index) or [`adapter_by_name()`](Self::adapter_by_name).
+    ///
+    /// This is useful to get an adapter to [scan](Coyote3::scan) with. If there is no such
+    /// adapter, this fails with [`Error::AdapterUnavailable`].
+    ///
+    /// ```ignore
+    /// let builder = Coyote3::connect().adapter_by_name("hci1");
+    /// let devices = Coyote3::scan(&builder.adapter().await?, Duration::from_secs(5)).await?;
+    /// ```
+    pub async fn adapter(&self) -> Result<Adapter> {
+        self.adapter.clone().resolve(self.manager.as_ref()).await
+    }
+    /// Connect to a specific [`btleplug::platform::Peripheral`].
+    pub fn to(mut self, peripheral: impl Into<Peripheral>) -> Self {
+        self.peripheral = Some(peripheral.into());
+        self
+    }
+    /// Only connect to the device with the given Bluetooth address.
+    ///
+    /// This is useful to pick a specific device if multiple devices are in range.
+    /// If a peripheral was given via [`to()`](Self::to), its address must match.
+    pub fn address(mut self, address: BDAddr) -> Self {
+        self.address = Some(address);
+        self
+    }
+    /// Look for devices advertising the given local name instead of the default `47L121000`.
+    ///
+    /// This may be needed for firmware revisions or regional variants that advertise a different
+    /// name.
+    pub fn device_name(mut self, name: impl Into<String>) -> Self {
+        self.device_name = Some(name.into());
+        self
+    }
+    /// Set the device settings.
+    ///
+    /// The settings are [validated](DeviceSettings::validate) when connecting, so invalid
+    /// settings make connecting fail with [`Error::InvalidSettings`].
+    pub fn settings(mut self, settings: DeviceSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+    /// Give up with [`Error::Timeout`] if scanning and connecting takes longer than `timeout`.
+    ///
+    /// By default, this waits until a matching device shows up.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Automatically reconnect when the connection to the device is lost.
+    ///
+    /// After a reconnect, the last [`DeviceSettings`] are applied again. Whether the device is
+    /// currently connected is reported by [`State::connected`].
+    ///
+    /// This spawns a background task, so the connection must be made from within a Tokio runtime.
+    pub fn auto_reconnect(mut self, auto_reconnect: AutoReconnect) -> Self {
+        self.auto_reconnect = Some(auto_reconnect);
+        self
+    }
+    /// Never request a stimulation intensity above `soft_limit`.
+    ///
+    /// In addition to the [limit](DeviceSettings::limit) enforced by the device, this rewrites
+    /// the intensity changes passed to [`Coyote3::send_pulses()`] so the resulting intensity,
+    /// projected from the current [intensity](Coyote3::intensity), never exceeds `soft_limit`.
+    /// This guards against bugs in the code that computes the intensity changes.
+    pub fn soft_limit(mut self, soft_limit: Stereo<u8>) -> Self {
+        self.soft_limit = Some(soft_limit);
+        self
+    }
+    /// Check that the output of each channel, combining its intensity and the pulse amplitudes,
+    /// stays within the [limit](DeviceSettings::limit) and the [soft limit](Self::soft_limit).
+    ///
+    /// See [`LimitCheck`] for the model used and what happens if the output would exceed the
+    /// limit. By default, there is no such check.
+    pub fn limit_check(mut self, check: LimitCheck) -> Self {
+        self.limit_check = Some(check);
+        self
+    }
+    /// Log a warning whenever the [limit](DeviceSettings::limit) of a channel is set above
+    /// `threshold`, both when connecting and when [updating the
+    /// settings](Coyote3::update_settings).
+    pub fn safety_threshold(mut self, threshold: u8) -> Self {
+        self.safety_threshold = Some(threshold);
+        self
+    }
+    /// Wait for the device to acknowledge each settings update.
+    ///
+    /// By default, settings updates are written without response like the pulses, so an update
+    /// can get lost silently on a bad connection. With this enabled, a lost update makes
+    /// [`Coyote3::update_settings()`] fail instead, at the cost of a round trip to the device,
+    /// which usually takes tens of milliseconds.
+    pub fn reliable_settings(mut self, reliable: bool) -> Self {
+        self.reliable_settings = reliable;
+        self
+    }
+    /// Swap channel A and B, for setups where the electrodes are wired the other way around.
+    ///
+    /// The channels of everything passed to [`Coyote3::send_pulses()`] and
+    /// [`Coyote3::update_settings()`] are swapped before sending it and the channels reported by
+    /// the device are swapped back, so the [state](Coyote3::state) and all other methods use the
+    /// swapped orientation throughout.
+    pub fn swap_channels(mut self, swap: bool) -> Self {
+        self.swap_channels = swap;
+        self
+    }
+    /// Connect to the device, but only log the commands at `info` level instead of sending them,
+    /// to develop control logic without any stimulation.
+    ///
+    /// Since the device never receives the commands, it does not report any changes of the
+    /// intensity or the settings. Instead, the [state](Coyote3::state) reflects the
+    /// [projected intensity](Coyote3::projected_intensity) and the settings that would have been
+    /// sent. Everything else, like the battery charge, is reported by the device as usual.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+    /// Send pulses every `interval` instead of every 100 ms, for experimental firmware that
+    /// accepts a different cadence.
+    ///
+    /// This affects [`Coyote3::play()`], [`Coyote3::start_stream()`] and
+    /// [`PulseStream::ramp_intensity()`]. Since each [`Pulses`] frame consists of four pulses,
+    /// `interval` must be a positive multiple of 4 ms, otherwise connecting fails with
+    /// [`Error::InvalidPulseInterval`].
+    pub fn pulse_interval(mut self, interval: Duration) -> Self {
+        self.pulse_interval = Some(interval);
+        self
+    }
+    /// Use `timer` for all timing instead of the Tokio time driver.
+    ///
+    /// This affects the periodic sending of pulses by [`Coyote3::play()`],
+    /// [`Coyote3::start_stream()`] and [`PulseStream::ramp_intensity()`], the playback of
+    /// [recordings](Coyote3::play_file), the connection [timeout](Self::timeout), the delays of
+    /// [automatic reconnection](Self::auto_reconnect) and [`Coyote3::connect_persistent()`],
+    /// and the timeout of [`Coyote3::update_settings_confirmed()`]. Connecting still requires a
+    /// Tokio runtime, see [`Timer`].
+    pub fn timer(mut self, timer: impl Timer) -> Self {
+        self.timer = Some(Arc::new(timer));
+        self
+    }
+    /// Remember the characteristics of the device in `cache` and reuse them when connecting to
+    /// the same address again, skipping the service discovery, which can take seconds.
+    ///
+    /// The cache is also used by [automatic reconnection](Self::auto_reconnect). If the cached
+    /// characteristics are incomplete or can't be used, the services are discovered again.
+    pub fn service_cache(mut self, cache: ServiceCache) -> Self {
+        self.service_cache = Some(cache);
+        self
+    }
+    /// Scan with the given filter when no peripheral was given via [`to()`](Self::to).
+    ///
+    /// By default, only devices advertising the main service of the Coyote 3 (0x180C) are
+    /// scanned for, which speeds up discovery in busy environments. Pass
+    /// `ScanFilter::default()` to scan for all devices instead.
+    pub fn scan_filter(mut self, filter: ScanFilter) -> Self {
+        self.scan_filter = Some(filter);
+        self
+    }
+    /// Restart the scan up to `retries` times if it ends before a device was found.
+    ///
+    /// On some platforms the adapter resets while scanning, which ends the scan. By default,
+    /// connecting then fails with [`Error::DeviceNotFound`] right away. Combine this with a
+    /// [`timeout()`](Self::timeout) to bound the total time spent scanning.
+    pub fn scan_retries(mut self, retries: u32) -> Self {
+        self.scan_retries = retries;
+        self
+    }
+    /// Let at most `depth` commands wait to be written to the device, 4 by default.
+    ///
+    /// If commands are sent faster than the device accepts them, e.g. by calling
+    /// [`Coyote3::send_pulses()`] in a tight loop, sending waits for room in the queue, which
+    /// keeps the latency from growing. Waiting pulses without an intensity change are replaced
+    /// by the next pulses sent instead, since only the latest waveform matters; their
+    /// `send_pulses()` completes successfully without them being written. A depth of 0 is
+    /// treated as 1.
+    pub fn write_queue_depth(mut self, depth: usize) -> Self {
+        self.write_queue_depth = Some(depth);
+        self
+    }
+    /// Call `callback` with each step of connecting, e.g. to show what is going on in a GUI.
+    ///
+    /// See [`ConnectProgress`] for the steps and their order. This only applies to connecting
+    /// initially, not to [automatic reconnection](Self::auto_reconnect).
+    ///
+    /// ```no_run
+    /// # use dungeonctl::Coyote3;
+    /// # #[tokio::main]
+    /// # async fn main() -> eyre::Result<()> {
+    /// Coyote3::connect()
+    ///     .on_progress(|progress| println!("{progress:?}"))
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn on_progress(
+        mut self,
+        callback: impl Fn(ConnectProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_progress = ProgressCallback(Some(Arc::new(callback)));
+        self
+    }
+    /// The timer to use, see [`timer()`](Self::timer).
+    fn effective_timer(&self) -> Arc<dyn Timer> {
+        self.timer.clone().unwrap_or_else(|| Arc::new(TokioTimer))
+    }
+    fn settings_write_type(&self) -> WriteType {
+        if self.reliable_settings {
+            WriteType::WithResponse
+        } else {
+            WriteType::WithoutResponse
+        }
+    }
+    /// The filter to scan with, see [`scan_filter()`](Self::scan_filter).
+    fn effective_scan_filter(&self) -> ScanFilter {
+        self.scan_filter.clone().unwrap_or_else(default_scan_filter)
+    }
+    fn filter(&self) -> PeripheralFilter<'_> {
+        PeripheralFilter {
+            local_name: self.device_name.as_deref().unwrap_or(DEVICE_NAME),
+            address: self.address,
+        }
+    }
+    async fn connect(mut self) -> Result<Coyote3> {
+        check_settings(&self.settings, self.safety_threshold)?;
+        let pulse_interval = check_pulse_interval(self.pulse_interval.unwrap_or(PULSE_INTERVAL))?;
+
+        let adapter = std::mem::take(&mut self.adapter)
+            .resolve(self.manager.as_ref())
+            .await?;
+        let peripheral = self.peripheral.take();
+        let filter = self.filter();
+        let peripheral = match peripheral {
+            Some(peripheral) => match self.address {
+                Some(expected) if expected != peripheral.address() => {
+                    return Err(Error::AddressMismatch {
+                        expected,
+                        actual: peripheral.address(),
+                    });
+                }
+                _ => peripheral,
+            },
+            None => {
+                self.on_progress.report(ConnectProgress::Scanning);
+                let scan = adapter
+                    .start_shared_scan(self.effective_scan_filter())
+                    .await?;
+
+                let (adapter, filter) = (&adapter, &filter);
+                let peripheral = find_peripheral(
+                    self.scan_retries,
+                    |attempt| async move {
+                        if attempt > 0 {
+                            debug!(attempt, "scan ended, restarting");
+                            adapter.restart_shared_scan().await?;
+                        }
+                        Ok(adapter.events().await?)
+                    },
+                    |event| async move {
+                        if let CentralEvent::DeviceDiscovered(id) = event {
+                            let peripheral = adapter.peripheral(&id).await?;
+                            // skip devices another `Coyote3` is connected to already
+                            if peripheral.matches(filter).await?
+                                && !peripheral.is_connected().await?
+                            {
+                                return Ok(Some(peripheral));
+                            }
+                        }
+                        Ok(None)
+                    },
+                )
+                .await;
+
+                scan.stop().await?;
+
+                peripheral?
+            }
+        };
+
+        self.on_progress
+            .report(ConnectProgress::Found(peripheral.address()));
+        let span = info_span!("coyote3", address = %peripheral.address());
+        self.set_up(adapter, peripheral, pulse_interval, span.clone())
+            .instrument(span)
+            .await
+    }
+    /// Connect to `peripheral` and set it up, with all logs of the device attributed to `span`.
+    async fn set_up(
+        self,
+        adapter: Adapter,
+        peripheral: Peripheral,
+        pulse_interval: Duration,
+        span: Span,
+    ) -> Result<Coyote3> {
+        let settings = self.settings;
+
+        debug!("connecting");
+        self.on_progress.report(ConnectProgress::Connecting);
+        peripheral.connect().await?;
+        let Characteristics {
+            battery,
+            write,
+            all,
+            ..
+        } = set_up(&peripheral, self.service_cache.as_ref(), &self.on_progress).await?;
+        let device_info = DeviceInfo::read(&peripheral, &all).await?;
+
+        let state = State {
+            battery: parse_battery(&peripheral.read(&battery).await?)?,
+            settings,
+            intensity: Stereo { a: 0, b: 0 },
+            rssi: peripheral.properties().await?.and_then(|p| p.rssi),
+            connected: true,
+        };
+
+        let adapter_updates = adapter.events().await?.filter_map({
+            let peripheral = peripheral.clone();
+
+            move |event| {
+                let peripheral = peripheral.clone();
+
+                async move {
+                    match event {
+                        CentralEvent::DeviceUpdated(id) if id == peripheral.id() => {
+                            match peripheral.properties().await {
+                                Ok(properties) => {
+                                    Some(Update::Rssi(properties.and_then(|p| p.rssi)))
+                                }
+                                Err(e) => {
+                                    error!(?e);
+                                    None
+                                }
+                            }
+                        }
+                        CentralEvent::DeviceConnected(id) if id == peripheral.id() => {
+                            Some(Update::Connected(true))
+                        }
+                        CentralEvent::DeviceDisconnected(id) if id == peripheral.id() => {
+                            Some(Update::Connected(false))
+                        }
+                        _ => None,
+                    }
+                }
+            }
+        });
+        let (local_updates, dry_run_updates) = futures::channel::mpsc::unbounded();
+        let updates = futures::stream::select(
+            futures::stream::select(
+                peripheral.notifications().await?.map(Update::Notification),
+                adapter_updates,
+            ),
+            dry_run_updates,
+        );
+
+        let projection = Arc::new(Mutex::new(Projection::new(state.intensity)));
+        let state = track_state(updates, state, self.swap_channels, span.clone());
+
+        let settings_handle = Arc::new(Mutex::new(settings));
+        let write = Arc::new(Mutex::new(write));
+        let reconnect = self.auto_reconnect.map(|config| {
+            Arc::new(ReconnectTask(tokio::spawn(
+                reconnect(
+                    adapter,
+                    peripheral.clone(),
+                    self.service_cache.clone(),
+                    config,
+                    self.effective_timer(),
+                    Restore {
+                        // nothing was written in a dry run, so there is nothing to restore
+                        settings: (!self.dry_run).then(|| settings_handle.clone()),
+                        write_type: self.settings_write_type(),
+                        swap_channels: self.swap_channels,
+                        write: write.clone(),
+                    },
+                )
+                .instrument(span.clone()),
+            )))
+        });
+
+        let coyote = Coyote3 {
+            peripheral: peripheral.clone(),
+            battery,
+            voltage: find_characteristic(&all, VOLTAGE_CHARACTERISTIC_UUID).ok(),
+            writer: {
+                let transport = PeripheralTransport {
+                    peripheral: peripheral.clone(),
+                    characteristic: write,
+                };
+                let depth = self.write_queue_depth.unwrap_or(WRITE_QUEUE_DEPTH);
+                if self.dry_run {
+                    Writer::spawn(DryRunTransport(transport), depth, span.clone())
+                } else {
+                    Writer::spawn(transport, depth, span.clone())
+                }
+            },
+            state,
+            settings: settings_handle,
+            reconnect,
+            soft_limit: self.soft_limit,
+            limit_check: self.limit_check,
+            safety_threshold: self.safety_threshold,
+            device_info: Arc::new(device_info),
+            enabled: Arc::new(Mutex::new(Stereo::symmetric(true))),
+            projection,
+            settings_write_type: self.settings_write_type(),
+            pulse_interval,
+            timer: self.effective_timer(),
+            swap_channels: self.swap_channels,
+            serials: Default::default(),
+            emergency: Default::default(),
+            pause: Default::default(),
+            send_times: Default::default(),
+            recorder: Default::default(),
+            dry_run: self.dry_run.then_some(local_updates),
+            span,
+        };
+
+        coyote.update_settings(settings).await?;
+        self.on_progress.report(ConnectProgress::Ready);
+
+        Ok(coyote)
+    }
+}
+
+/// A step of connecting to a Coyote 3, see [`Coyote3Builder::on_progress()`].
+///
+/// The steps are reported in the order they are declared in, skipping the ones that don't
+/// apply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectProgress {
+    /// Scanning for the device. This is skipped if a peripheral was given via
+    /// [`Coyote3Builder::to()`].
+    Scanning,
+    /// The device with this address was found.
+    Found(BDAddr),
+    /// Connecting to the device.
+    Connecting,
+    /// Discovering the services of the device. This is skipped if the characteristics are
+    /// taken from the [service cache](Coyote3Builder::service_cache).
+    DiscoveringServices,
+    /// Subscribing to the notifications of the device. If cached characteristics turn out to be
+    /// outdated, the services are discovered and this step is reported again.
+    Subscribing,
+    /// The device is connected and set up.
+    Ready,
+}
+
+/// The callback passed to [`Coyote3Builder::on_progress()`], if any.
+#[derive(Clone, Default)]
+struct ProgressCallback(Option<Arc<dyn Fn(ConnectProgress) + Send + Sync>>);
+
+impl ProgressCallback {
+    fn report(&self, progress: ConnectProgress) {
+        if let Some(callback) = &self.0 {
+            callback(progress);
+        }
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ProgressCallback")
+            .field(&self.0.as_ref().map(|_| ".."))
+            .finish()
+    }
+}
+
+/// Return the first item `select` picks out of the events of a scan, starting the scan again up
+/// to `retries` times if its events end.
+///
+/// `scan` is called with the number of the attempt, starting at 0.
+async fn find_peripheral<E, T, S, Scan, Select>(
+    retries: u32,
+    mut scan: impl FnMut(u32) -> Scan,
+    mut select: impl FnMut(E) -> Select,
+) -> Result<T>
+where
+    S: Stream<Item = E> + Unpin,
+    Scan: Future<Output = Result<S>>,
+    Select: Future<Output = Result<Option<T>>>,
+{
+    for attempt in 0..=retries {
+        let mut events = scan(attempt).await?;
+
+        while let Some(event) = events.next().await {
+            if let Some(found) = select(event).await? {
+                return Ok(found);
+            }
+        }
+    }
+
+    Err(Error::DeviceNotFound)
+}
+
+/// Scan only for devices advertising the main service of the Coyote 3.
+fn default_scan_filter() -> ScanFilter {
+    ScanFilter {
+        services: vec![MAIN_SERVICE_UUID],
+    }
+}
+
+/// Keep track of the state of a single connection by applying its `updates` to `state` in a
+/// spawned task.
+///
+/// If `swap_channels` is set, the updates are applied in the orientation of the device and
+/// reported in the swapped orientation, see [`Coyote3Builder::swap_channels()`].
+fn track_state(
+    updates: impl Stream<Item = Update> + Send + 'static,
+    state: State,
+    swap_channels: bool,
+    span: Span,
+) -> DeviceState<State> {
+    let mut device = state.oriented(swap_channels);
+
+    DeviceState::spawn(
+        updates.filter_map(move |update| {
+            let changed = span.in_scope(|| device.apply(update));
+            std::future::ready(changed.then(|| device.oriented(swap_channels)))
+        }),
+        state,
+    )
+}
+
+/// Make sure the four pulses of a frame can each take a whole number of milliseconds.
+fn check_pulse_interval(interval: Duration) -> Result<Duration> {
+    let valid = !interval.is_zero() && interval.as_nanos().is_multiple_of(4_000_000);
+    if !valid {
+        return Err(Error::InvalidPulseInterval(interval));
+    }
+
+    Ok(interval)
+}
+
+/// How far the battery charge has to rise above a threshold to leave the corresponding
+/// [`BatteryEvent`] state, in percent.
+const BATTERY_HYSTERESIS: u8 = 5;
+
+/// Set `field` to `value`, returning whether it changed.
+fn replace<T: PartialEq>(field: &mut T, value: T) -> bool {
+    if *field == value {
+        return false;
+    }
+    *field = value;
+    true
+}
+
+/// Only report RSSI changes of at least this many dBm, to not flood the state signal with noise.
+const RSSI_HYSTERESIS: i16 = 5;
+
+fn rssi_changed(old: Option<i16>, new: Option<i16>) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old.abs_diff(new) >= RSSI_HYSTERESIS as u16,
+        (old, new) => old != new,
+    }
+}
+
+/// Which adapter [`Coyote3Builder`] connects with.
+#[derive(Clone, Debug, Default)]
+enum AdapterChoice {
+    #[default]
+    First,
+    Given(Adapter),
+    Index(usize),
+    Name(String),
+}
+
+impl AdapterChoice {
+    /// Pick an adapter by its index in the list of available adapters, given their infos.
+    fn select(&self, infos: &[String]) -> Option<usize> {
+        let index = match self {
+            AdapterChoice::First | AdapterChoice::Given(_) => 0,
+            AdapterChoice::Index(index) => *index,
+            AdapterChoice::Name(name) => infos.iter().position(|info| {
+                info.strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+            })?,
+        };
+
+        (index < infos.len()).then_some(index)
+    }
+    /// Get the chosen adapter, enumerating the adapters of `manager` or of a new manager unless
+    /// the adapter was given.
+    async fn resolve(self, manager: Option<&Manager>) -> Result<Adapter> {
+        let choice = match self {
+            AdapterChoice::Given(adapter) => return Ok(adapter),
+            choice => choice,
+        };
+
+        let adapters = match manager {
+            Some(manager) => manager.adapters().await?,
+            None => Manager::new().await?.adapters().await?,
+        };
+        let mut infos = Vec::with_capacity(adapters.len());
+        for adapter in &adapters {
+            infos.push(adapter.adapter_info().await?);
+        }
+
+        choice.pick(adapters, &infos)
+    }
+    /// Take the adapter out of `adapters` that [`select()`](Self::select) picks, given their
+    /// infos.
+    fn pick<A>(&self, mut adapters: Vec<A>, infos: &[String]) -> Result<A> {
+        let index = self.select(infos).ok_or(Error::AdapterUnavailable)?;
+        Ok(adapters.swap_remove(index))
+    }
+}
+
+/// Configures the automatic reconnection, see [`Coyote3Builder::auto_reconnect()`].
+///
+/// The delay before each reconnection attempt starts at `initial_delay` and doubles with every
+/// failed attempt, up to `max_delay`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, SmartDefault)]
+pub struct AutoReconnect {
+    /// The delay before the first reconnection attempt.
+    #[default(Duration::from_millis(500))]
+    pub initial_delay: Duration,
+    /// The maximum delay between two reconnection attempts.
+    #[default(Duration::from_secs(30))]
+    pub max_delay: Duration,
+    /// Give up after this many failed attempts. By default, this never gives up.
+    pub max_attempts: Option<u32>,
+}
+
+impl AutoReconnect {
+    /// The delay before the given reconnection attempt, starting at 0.
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay)
+    }
+}
+
+/// What to do when the output of a channel would exceed its limit, see
+/// [`Coyote3Builder::limit_check()`].
+///
+/// The output of a channel is modeled as its intensity scaled by the amplitude of the pulses:
+/// `intensity × amplitude / 100`, using the highest amplitude of the four pulses of a frame and
+/// the [projected intensity](Coyote3::projected_intensity) after the intensity change of the
+/// frame is applied. Since intensity changes are already kept within the limit, this only
+/// catches a channel intensity above the limit, e.g. after the limit was lowered or when the
+/// device reports an intensity the projection did not expect.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitCheck {
+    /// Lower the amplitudes of the pulses so the output stays within the limit.
+    Clamp,
+    /// Don't send the pulses and fail with [`Error::LimitExceeded`].
+    Reject,
+}
+
+/// Values that differ per channel, which are swapped by [`Coyote3Builder::swap_channels()`].
+trait SwapChannels: Sized {
+    /// Exchange everything of channel A with channel B.
+    fn swapped(self) -> Self;
+    /// Swap the channels if `swap` is set.
+    fn oriented(self, swap: bool) -> Self {
+        if swap { self.swapped() } else { self }
+    }
+}
+
+impl<T: Copy> SwapChannels for Stereo<T> {
+    fn swapped(self) -> Self {
+        self.swap()
+    }
+}
+
+impl SwapChannels for Pulses {
+    fn swapped(self) -> Self {
+        Self {
+            intensity: self.intensity.swap(),
+            pulses: self.pulses.map(Stereo::swap),
+        }
+    }
+}
+
+impl SwapChannels for DeviceSettings {
+    fn swapped(self) -> Self {
+        Self {
+            limit: self.limit.swap(),
+            frequency_balance: self.frequency_balance.swap(),
+            intensity_balance: self.intensity_balance.swap(),
+        }
+    }
+}
+
+impl SwapChannels for Event {
+    fn swapped(self) -> Self {
+        match self {
+            Event::IntensityChanged {
+                source,
+                origin,
+                intensity,
+            } => Event::IntensityChanged {
+                source,
+                origin,
+                intensity: intensity.swap(),
+            },
+            Event::SettingsDrift { intended, reported } => Event::SettingsDrift {
+                intended: intended.swapped(),
+                reported: reported.swapped(),
+            },
+            event @ Event::Unknown { .. } => event,
+        }
+    }
+}
+
+impl SwapChannels for State {
+    fn swapped(self) -> Self {
+        Self {
+            intensity: self.intensity.swap(),
+            settings: self.settings.swapped(),
+            ..self
+        }
+    }
+}
+
+/// Aborts the reconnection task once the last clone of the [`Coyote3`] is dropped.
+#[derive(Debug)]
+struct ReconnectTask(tokio::task::JoinHandle<()>);
+
+impl Drop for ReconnectTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// What [`reconnect()`] restores once the connection is back.
+struct Restore {
+    /// The settings to write again, if any.
+    settings: Option<Arc<Mutex<DeviceSettings>>>,
+    write_type: WriteType,
+    swap_channels: bool,
+    /// The write characteristic used by the `Coyote3`, which is replaced with the rediscovered
+    /// one.
+    write: Arc<Mutex<Characteristic>>,
+}
+
+async fn reconnect(
+    adapter: Adapter,
+    peripheral: Peripheral,
+    cache: Option<ServiceCache>,
+    config: AutoReconnect,
+    timer: Arc<dyn Timer>,
+    restore: Restore,
+) {
+    let mut events = match adapter.events().await {
+        Ok(events) => events,
+        Err(e) => {
+            error!(?e, "failed to watch for disconnects, not reconnecting");
+            return;
+        }
+    };
+
+    while let Some(event) = events.next().await {
+        if !matches!(event, CentralEvent::DeviceDisconnected(id) if id == peripheral.id()) {
+            continue;
+        }
+
+        debug!("connection lost, reconnecting");
+
+        let mut attempt = 0;
+        loop {
+            sleep(&*timer, config.delay(attempt)).await;
+
+            let settings = restore
+                .settings
+                .as_ref()
+                .map(|settings| settings.lock().unwrap().oriented(restore.swap_channels));
+            match reconnect_once(
+                &adapter,
+                &peripheral,
+                settings,
+                restore.write_type,
+                cache.as_ref(),
+            )
+            .await
+            {
+                Ok(write) => {
+                    *restore.write.lock().unwrap() = write;
+                    break;
+                }
+                Err(e) => error!(?e, attempt, "failed to reconnect"),
+            }
+
+            attempt += 1;
+            if config.max_attempts.is_some_and(|max| attempt >= max) {
+                error!("giving up reconnecting after {attempt} attempts");
+                return;
+            }
+        }
+
+        debug!("reconnected");
+    }
+}
+
+async fn reconnect_once(
+    adapter: &Adapter,
+    peripheral: &Peripheral,
+    settings: Option<DeviceSettings>,
+    write_type: WriteType,
+    cache: Option<&ServiceCache>,
+) -> Result<Characteristic> {
+    // scanning makes the device known to the platform again if it went out of range, the scan
+    // is shared so it doesn't stop the scan of a `connect()` running at the same time
+    let scan = adapter.start_shared_scan(default_scan_filter()).await?;
+    let result = async {
+        peripheral.connect().await?;
+        let Characteristics { write, .. } =
+            set_up(peripheral, cache, &ProgressCallback::default()).await?;
+        if let Some(settings) = settings {
+            peripheral
+                .write(
+                    &write,
+                    &Command::UpdateSettings(settings).to_bytes()?,
+                    write_type,
+                )
+                .await?;
+        }
+
+        Ok(write)
+    }
+    .await;
+    scan.stop().await?;
+
+    result
+}
+
+/// Information about the connected device. This can be obtained by calling
+/// [`Coyote3::device_info()`].
+///
+/// This is read from the standard Device Information service. The Coyote 3 uses this service
+/// for its battery level as well and might not provide all of these fields, in which case they
+/// are `None`.
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct DeviceInfo {
+    /// The model number.
+    pub model: Option<String>,
+    /// The firmware revision.
+    pub firmware_revision: Option<String>,
+    /// The hardware revision.
+    pub hardware_revision: Option<String>,
+}
+
+impl DeviceInfo {
+    async fn read(
+        peripheral: &Peripheral,
+        characteristics: &BTreeSet<Characteristic>,
+    ) -> Result<Self> {
+        let read = async |uuid| -> Result<Option<String>> {
+            match find_characteristic(characteristics, uuid) {
+                Ok(characteristic) => Ok(Some(parse_info_string(
+                    &peripheral.read(&characteristic).await?,
+                ))),
+                Err(_) => {
+                    debug!("device does not provide characteristic {uuid}");
+                    Ok(None)
+                }
+            }
+        };
+
+        Ok(Self {
+            model: read(MODEL_NUMBER_CHARACTERISTIC_UUID).await?,
+            firmware_revision: read(FIRMWARE_REVISION_CHARACTERISTIC_UUID).await?,
+            hardware_revision: read(HARDWARE_REVISION_CHARACTERISTIC_UUID).await?,
+        })
+    }
+}
+
+fn parse_battery(value: &[u8]) -> Result<u8> {
+    Ok(u8::read(&mut binrw::io::Cursor::new(value))?)
+}
+
+/// Parse a value of the Voltage characteristic, which is given in units of 1/64 V, into
+/// millivolts, saturating above 65.535 V.
+fn parse_voltage(value: &[u8]) -> Result<u16> {
+    let raw = u16::read_le(&mut binrw::io::Cursor::new(value))?;
+    Ok((raw as u32 * 1000 / 64).min(u16::MAX as u32) as u16)
+}
+
+fn parse_info_string(value: &[u8]) -> String {
+    String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .trim()
+        .to_owned()
+}
+
+#[derive(Clone, Debug)]
+struct Characteristics {
+    battery: Characteristic,
+    notify: Characteristic,
+    write: Characteristic,
+    /// All characteristics of the device.
+    all: BTreeSet<Characteristic>,
+}
+
+impl Characteristics {
+    fn find(all: BTreeSet<Characteristic>) -> Result<Self> {
+        Ok(Self {
+            battery: find_characteristic(&all, BATTERY_CHARACTERISTIC_UUID)?,
+            notify: find_characteristic(&all, NOTIFY_CHARACTERISTIC_UUID)?,
+            write: find_characteristic(&all, WRITE_CHARACTERISTIC_UUID)?,
+            all,
+        })
+    }
+    async fn subscribe(&self, peripheral: &Peripheral) -> Result<()> {
+        peripheral.subscribe(&self.battery).await?;
+        peripheral.subscribe(&self.notify).await?;
+        Ok(())
+    }
+}
+
+/// Remembers the characteristics of devices by their address, so connecting to them again can
+/// skip the service discovery. See [`Coyote3Builder::service_cache()`].
+///
+/// Clones share the same cache.
+#[derive(Clone, Debug, Default)]
+pub struct ServiceCache(Arc<Mutex<HashMap<BDAddr, BTreeSet<Characteristic>>>>);
+
+impl ServiceCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Forget the characteristics of all devices.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+    /// Get the cached characteristics of the device at `address`, if all required ones are
+    /// known.
+    fn get(&self, address: BDAddr) -> Option<Characteristics> {
+        let all = self.0.lock().unwrap().get(&address)?.clone();
+        Characteristics::find(all).ok()
+    }
+    fn insert(&self, address: BDAddr, characteristics: BTreeSet<Characteristic>) {
+        self.0.lock().unwrap().insert(address, characteristics);
+    }
+    fn remove(&self, address: BDAddr) {
+        self.0.lock().unwrap().remove(&address);
+    }
+}
+
+/// Discover the characteristics of a connected device, or take them from `cache`, and subscribe
+/// to its notifications.
+async fn set_up(
+    peripheral: &Peripheral,
+    cache: Option<&ServiceCache>,
+    progress: &ProgressCallback,
+) -> Result<Characteristics> {
+    let address = peripheral.address();
+
+    if let Some(cache) = cache
+        && let Some(characteristics) = cache.get(address)
+    {
+        debug!("using cached characteristics");
+        progress.report(ConnectProgress::Subscribing);
+        match characteristics.subscribe(peripheral).await {
+            Ok(()) => return Ok(characteristics),
+            Err(e) => {
+                debug!(?e, "cached characteristics can't be used");
+                cache.remove(address);
+            }
+        }
+    }
+
+    debug!("discovering services");
+    progress.report(ConnectProgress::DiscoveringServices);
+    peripheral.discover_services().await?;
+
+    let characteristics = Characteristics::find(peripheral.characteristics())?;
+    progress.report(ConnectProgress::Subscribing);
+    characteristics.subscribe(peripheral).await?;
+
+    if let Some(cache) = cache {
+        cache.insert(address, characteristics.all.clone());
+    }
+
+    Ok(characteristics)
+}
+
+fn find_characteristic(
+    characteristics: &BTreeSet<Characteristic>,
+    uuid: Uuid,
+) -> Result<Characteristic> {
+    characteristics
+        .iter()
+        .find(|characteristic| characteristic.uuid == uuid)
+        .cloned()
+        .ok_or(Error::MissingCharacteristic(uuid))
+}
+
+impl IntoFuture for Coyote3Builder {
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+    type Output = Result<Coyote3>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        match self.timeout {
+            Some(duration) => async move {
+                let timer = self.effective_timer();
+                timeout(&*timer, duration, self.connect())
+                    .await
+                    .unwrap_or(Err(Error::Timeout))
+            }
+            .boxed(),
+            None => self.connect().boxed(),
+        }
+    }
+}
+
+impl Coyote3 {
+    /// Get the intensity the device is expected to have once the pulses sent so far are applied.
+    ///
+    /// This is estimated from the intensity changes sent with
+    /// [`send_pulses()`](Self::send_pulses). Whenever the device reports a different
+    /// [intensity](Self::intensity), e.g. because it was changed using the hardware buttons, the
+    /// estimate starts over from the reported value.
+    pub fn projected_intensity(&self) -> Stereo<u8> {
+        let mut projection = self.projection.lock().unwrap();
+        projection.reconcile(self.state.get().intensity);
+        projection.projected
+    }
+    /// Get the state of the connected Coyote3.
+    ///
+    /// This returns a reactive signal that can either be
+    /// used via the [`SignalExt`](futures_signals::signal::SignalExt) trait or the current value
+    /// can be obtained using its [`get()`](crate::StateSignal::get) method.
+    pub fn state(&self) -> impl StateSignal<State> {
+        self.state.clone()
+    }
+    /// Get the battery charge of the connected Coyote3 in percent.
+    ///
+    /// Unlike [`state()`](Self::state), this signal only changes when the battery charge changes.
+    pub fn battery(&self) -> impl StateSignal<u8> {
+        self.state.derive(|state| state.battery)
+    }
+    /// Read the battery charge of the connected Coyote3 in percent.
+    ///
+    /// Unlike [`battery()`](Self::battery), this reads the value from the device on each call,
+    /// so it should not be called in a tight loop.
+    pub async fn read_battery(&self) -> Result<u8> {
+        parse_battery(&self.peripheral.read(&self.battery).await?)
+    }
+    /// Read the battery voltage of the connected Coyote3 in millivolts.
+    ///
+    /// This depends on the firmware: the voltage is only available if the device provides the
+    /// standard Bluetooth Voltage characteristic (`0x2B18`), which is not part of the documented
+    /// protocol. Known firmware revisions only report the [charge in percent](Self::battery), in
+    /// which case this returns `Ok(None)`.
+    pub async fn battery_voltage(&self) -> Result<Option<u16>> {
+        let Some(characteristic) = &self.voltage else {
+            return Ok(None);
+        };
+
+        parse_voltage(&self.peripheral.read(characteristic).await?).map(Some)
+    }
+    /// Get the last known device settings of the connected Coyote3.
+    ///
+    /// This returns the cached value from the [`state()`](Self::state), it does not read the
+    /// settings from the device.
+    pub fn settings(&self) -> DeviceSettings {
+        self.state.get().settings
+    }
+    /// Get the device settings of the connected Coyote3 as a signal.
+    ///
+    /// Unlike [`state()`](Self::state), this signal only changes when the settings change, not
+    /// when e.g. the intensity or battery charge does. To bind a single parameter, map the
+    /// signal and deduplicate it again:
+    ///
+    /// ```ignore
+    /// use dungeonctl::futures_signals::signal::SignalExt;
+    ///
+    /// let limit_a = coyote.settings_signal().map(|settings| settings.limit.a).dedupe();
+    /// ```
+    pub fn settings_signal(&self) -> impl StateSignal<DeviceSettings> {
+        self.state.derive(|state| state.settings)
+    }
+    /// Get the stimulation intensity of the connected Coyote3.
+    ///
+    /// Unlike [`state()`](Self::state), this signal only changes when the intensity changes.
+    /// To tell whether a change was caused by this client, see the [`origin`] of the
+    /// [`events()`](Self::events) instead.
+    ///
+    /// [`origin`]: IntensityOrigin
+    pub fn intensity(&self) -> impl StateSignal<Stereo<u8>> {
+        self.state.derive(|state| state.intensity)
+    }
+    /// Get whether the Coyote3 is still connected.
+    ///
+    /// This is `true` right after connecting and changes whenever the connection is lost or
+    /// restored, e.g. by [automatic reconnection](Coyote3Builder::auto_reconnect).
+    pub fn connected(&self) -> impl StateSignal<bool> {
+        self.state.derive(|state| state.connected)
+    }
+    /// Get the highest frequency the connected Coyote3 supports, to scale generated waveforms to.
+    ///
+    /// The official documentation states a maximum of 100 Hz, but all known firmware revisions
+    /// accept up to 200 Hz, so this is always [`Frequency::MAX`] for now. It may be derived from
+    /// the [firmware revision](DeviceInfo::firmware_revision) once a revision is known to differ.
+    pub fn max_frequency(&self) -> Frequency {
+        Frequency::MAX
+    }
+    /// Get the platform identifier of the peripheral this handle is connected to.
+    ///
+    /// Unlike the [address](Self::address), this is stable on all platforms, e.g. macOS doesn't
+    /// expose the actual address of a device. Handles compare equal and hash the same if they
+    /// have the same identifier, see [`Coyote3`].
+    pub fn id(&self) -> PeripheralId {
+        self.peripheral.id()
+    }
+    /// Get the Bluetooth address of the connected Coyote3.
+    ///
+    /// On macOS, this is always `00:00:00:00:00:00`, use [`id()`](Self::id) to tell devices
+    /// apart.
+    pub fn address(&self) -> BDAddr {
+        self.peripheral.address()
+    }
+    /// Get the model and revision information of the connected Coyote3.
+    ///
+    /// This was read once when connecting.
+    pub fn device_info(&self) -> DeviceInfo {
+        (*self.device_info).clone()
+    }
+    /// Get a stream of the events reported by the connected Coyote3.
+    ///
+    /// Unlike the signals, which only provide the latest value, this stream yields every single
+    /// event, e.g. each intensity change caused by the “shoulder” switches of the coyote.
+    ///
+    /// Notifications that could not be parsed are yielded as [`Error::Protocol`].
+    pub async fn events(&self) -> Result<impl Stream<Item = Result<Event>> + use<>> {
+        let swap_channels = self.swap_channels;
+        let serials = self.serials.clone();
+        let mut outstanding = Outstanding::new(&serials.lock().unwrap());
+        let settings = self.settings.clone();
+
+        Ok(self
+            .peripheral
+            .notifications()
+            .await?
+            .filter_map(move |notification| {
+                let intended = settings.lock().unwrap().oriented(swap_channels);
+                let event = Event::from_notification(
+                    &notification,
+                    |serial| outstanding.report(&serials.lock().unwrap(), serial),
+                    intended,
+                )
+                .map(|event| event.map(|event| event.oriented(swap_channels)));
+                std::future::ready(event)
+            }))
+    }
+    /// Get a stream of events for when the battery charge crosses the `low` and `critical`
+    /// thresholds in percent, where `critical` is expected to be below `low`.
+    ///
+    /// An event is emitted when the charge drops to or below a threshold, including right away
+    /// if it already is. To avoid flapping around a threshold, the charge has to rise 5 percent
+    /// above it again before it counts as crossed, e.g. when charging. Once the charge rises
+    /// above `low` that way, [`BatteryEvent::Recovered`] is emitted.
+    pub fn battery_events(
+        &self,
+        low: u8,
+        critical: u8,
+    ) -> impl Stream<Item = BatteryEvent> + use<> {
+        let battery = self.state.derive(|state| state.battery);
+        let mut monitor = BatteryMonitor::new(low, critical);
+
+        futures::stream::once(std::future::ready(battery.get()))
+            .chain(futures_signals::signal::SignalExt::to_stream(battery))
+            .filter_map(move |battery| std::future::ready(monitor.update(battery)))
+    }
+    /// Get a stream of all notifications sent by the connected Coyote3, as the UUID of the
+    /// characteristic and the raw payload.
+    ///
+    /// This is meant for debugging and exploring undocumented packets, parsing them is up to the
+    /// caller. Use [`events()`](Self::events) or the signals instead to observe the device.
+    pub async fn raw_notifications(&self) -> Result<impl Stream<Item = (Uuid, Vec<u8>)> + use<>> {
+        Ok(self
+            .peripheral
+            .notifications()
+            .await?
+            .map(|notification| (notification.uuid, notification.value)))
+    }
+    /// Send the next pulses to the Coyote 3.
+    ///
+    /// This is expected to be called every 100 ms and
+    /// provides the signal data for the next four 25 ms pulses.
+    ///
+    /// Intensity changes are limited so the [projected
+    /// intensity](Self::projected_intensity) stays within the [limit](DeviceSettings::limit) and
+    /// the [soft limit](Coyote3Builder::soft_limit), if one is set.
+    ///
+    /// This is cancel-safe: commands are written by a background task, so once this was polled,
+    /// the command is written completely even if the returned future is dropped. If it was still
+    /// waiting for room in the [write queue](Coyote3Builder::write_queue_depth), it is not
+    /// written at all.
+    ///
+    /// Disabled channels are [muted](Self::set_channel_enabled). While an
+    /// [emergency stop](Self::emergency_stop) is in effect, this fails with
+    /// [`Error::EmergencyStopped`], and while the output is [paused](Self::pause) with
+    /// [`Error::Paused`].
+    pub async fn send_pulses(&self, pulses: Pulses) -> Result<()> {
+        self.pause.lock().unwrap().check()?;
+        self.send_pulses_forced(pulses).await
+    }
+    /// Send the next pulses like [`send_pulses()`](Self::send_pulses), even while the output is
+    /// [paused](Self::pause).
+    ///
+    /// This does not resume the output, but the pulses are resumed with afterwards. An
+    /// [emergency stop](Self::emergency_stop) still applies.
+    pub async fn send_pulses_forced(&self, pulses: Pulses) -> Result<()> {
+        self.emergency.lock().unwrap().check()?;
+        self.write_pulses(pulses).await?;
+        self.pause.lock().unwrap().record(pulses);
+
+        Ok(())
+    }
+    /// Send the next pulses to the Coyote 3 with as little overhead as possible, for control
+    /// loops that keep track of the intensity themselves.
+    ///
+    /// Unlike [`send_pulses()`](Self::send_pulses), this writes `pulses` as they are: the
+    /// intensity change is only limited by the device itself, up to the
+    /// [limit](DeviceSettings::limit). The only check is for an
+    /// [emergency stop](Self::emergency_stop), which still makes this fail. All others are
+    /// skipped:
+    ///
+    /// - the check for a [pause](Self::pause), so pulses are sent even while paused,
+    /// - the [soft limit](Coyote3Builder::soft_limit),
+    /// - the [limit check](Coyote3Builder::limit_check) and
+    /// - [disabled channels](Self::set_channel_enabled), which are not silenced.
+    ///
+    /// Nothing is tracked either: the [projected intensity](Self::projected_intensity),
+    /// [`last_sent()`](Self::last_sent) and [`jitter()`](Self::jitter) don't account for these
+    /// pulses, and since the command carries no serial number, the resulting intensity changes
+    /// are reported like ones made on the device itself. [`state()`](Self::state) only catches up
+    /// once the device reports the change, so it may lag behind the pulses sent.
+    pub async fn send_pulses_raw(&self, pulses: &Pulses) -> Result<()> {
+        send_raw(&self.writer, &self.emergency, pulses, self.swap_channels).await
+    }
+    /// Send `pulses` regardless of an emergency stop.
+    async fn write_pulses(&self, pulses: Pulses) -> Result<()> {
+        let limit = self.settings.lock().unwrap().limit;
+        let limit = match self.soft_limit {
+            Some(soft_limit) => limit.zip_with(soft_limit, u8::min),
+            None => limit,
+        };
+        let pulses = pulses.muted(*self.enabled.lock().unwrap());
+        let (pulses, previous, applied) = {
+            let mut projection = self.projection.lock().unwrap();
+            let previous = *projection;
+            let pulses = projection.apply(self.state.get().intensity, pulses, limit);
+
+            let pulses = match self.limit_check {
+                Some(check) => match pulses.checked_output(projection.projected, limit, check) {
+                    Ok(pulses) => pulses,
+                    Err(e) => {
+                        // the pulses are not sent, so they don't change the intensity
+                        *projection = previous;
+                        return Err(e);
+                    }
+                },
+                None => pulses,
+            };
+            (pulses, previous, *projection)
+        };
+
+        let recorded = pulses;
+        let pulses = pulses.oriented(self.swap_channels);
+        let command = if pulses.intensity == Pulses::silent().intensity {
+            Command::SendPulses(pulses)
+        } else {
+            let serial = self.serials.lock().unwrap().issue();
+            Command::SendTrackedPulses { serial, pulses }
+        };
+        if let Err(e) = self.writer.send(command, WriteType::WithoutResponse).await {
+            // the pulses were not sent, so they don't change the intensity either
+            self.projection.lock().unwrap().undo(applied, previous);
+            return Err(e);
+        }
+        self.send_times.lock().unwrap().record(self.timer.now());
+        if let Some(recorder) = &mut *self.recorder.lock().unwrap()
+            && let Err(e) = recorder.record(self.timer.now(), &recorded)
+        {
+            error!(?e, "failed to record pulses");
+        }
+        self.report_dry_run(Update::Intensity(
+            applied.projected.oriented(self.swap_channels),
+        ));
+
+        Ok(())
+    }
+    /// Get the time the last pulses were written to the device, or `None` if none were sent yet.
+    ///
+    /// The time is taken from the configured [timer](Coyote3Builder::timer).
+    pub fn last_sent(&self) -> Option<Instant> {
+        self.send_times.lock().unwrap().last
+    }
+    /// Get how much the time between the last 10 pulses sent deviated from the
+    /// [pulse interval](Coyote3Builder::pulse_interval) on average, or `None` if fewer than two
+    /// pulses were sent.
+    ///
+    /// A high jitter means that the pulses are not sent in time, e.g. because the control loop
+    /// stalls or the Bluetooth connection is congested, which makes the output stutter.
+    pub fn jitter(&self) -> Option<Duration> {
+        self.send_times.lock().unwrap().jitter(self.pulse_interval)
+    }
+    /// Report the change a command would have made in a dry run, like the device would.
+    fn report_dry_run(&self, update: Update) {
+        if let Some(updates) = &self.dry_run {
+            // the receiver lives as long as `self.state`
+            let _ = updates.unbounded_send(update);
+        }
+    }
+    /// Enable or disable a channel.
+    ///
+    /// While a channel is disabled, all pulses sent to it are silent and its intensity is set to
+    /// zero, regardless of what is passed to [`send_pulses()`](Self::send_pulses). This takes
+    /// effect with the next pulses that are sent. After enabling the channel again, its intensity
+    /// needs to be raised again.
+    pub fn set_channel_enabled(&self, channel: Channel, enabled: bool) {
+        *self.enabled.lock().unwrap().get_mut(channel) = enabled;
+    }
+    /// Send the pulses yielded by `frames`, one every 100 ms (or the configured
+    /// [pulse interval](Coyote3Builder::pulse_interval)), until the stream ends.
+    ///
+    /// The next frame is only pulled from the stream when it is due, so the stream can produce
+    /// the frames lazily. If the stream can't keep up, the following frames are delayed.
+    ///
+    /// ```ignore
+    /// coyote
+    ///     .play(futures::stream::iter((0..50).map(|i| Pattern::Wave.pulses_at(i as f32 * 0.1, 80))))
+    ///     .await?;
+    /// ```
+    pub async fn play(&self, frames: impl Stream<Item = Pulses>) -> Result<()> {
+        let interval = Interval::new(self.timer.clone(), self.pulse_interval);
+        play(frames, interval, |pulses| self.send_pulses(pulses)).await
+    }
+    /// Start recording the pulses sent to the file at `path`, so they can be played back using
+    /// [`play_file()`](Self::play_file).
+    ///
+    /// All pulses written to the device are recorded with the time they were sent at, including
+    /// those sent by [`play()`](Self::play), [`start_stream()`](Self::start_stream),
+    /// [`stop()`](Self::stop), [`pause()`](Self::pause) and
+    /// [`emergency_stop()`](Self::emergency_stop). They are recorded as they were sent, after
+    /// [muting](Self::set_channel_enabled) and limiting the intensity, but before
+    /// [swapping the channels](Coyote3Builder::swap_channels), so playing them back with the same
+    /// orientation reproduces the output. Pulses sent with
+    /// [`send_pulses_raw()`](Self::send_pulses_raw) are not recorded. The file is created or
+    /// truncated, and a recording already in progress is stopped.
+    ///
+    /// The recording continues until [`stop_recording()`](Self::stop_recording) is called or
+    /// the last handle to the device is dropped. Failing to write the file is logged, it doesn't
+    /// stop the pulses from being sent.
+    pub fn record(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let file = std::fs::File::create(path).map_err(Error::Recording)?;
+        let recorder = Recorder::new(std::io::BufWriter::new(file)).map_err(Error::Recording)?;
+
+        let previous = self.recorder.lock().unwrap().replace(recorder);
+        if let Some(previous) = previous {
+            previous.finish().map_err(Error::Recording)?;
+        }
+        Ok(())
+    }
+    /// Stop the recording started with [`record()`](Self::record) and write the rest of it to
+    /// the file. Does nothing if nothing is being recorded.
+    pub fn stop_recording(&self) -> Result<()> {
+        let recorder = self.recorder.lock().unwrap().take();
+        if let Some(recorder) = recorder {
+            recorder.finish().map_err(Error::Recording)?;
+        }
+        Ok(())
+    }
+    /// Play back the pulses [recorded](Self::record) to the file at `path`, sending each of them
+    /// using [`send_pulses()`](Self::send_pulses) at the same time relative to the first one as
+    /// they were recorded at.
+    ///
+    /// The whole file is read on a blocking thread before the first pulses are sent, so an
+    /// invalid recording fails with [`Error::Recording`] without any output.
+    ///
+    /// ```ignore
+    /// coyote.record("session.dglr")?;
+    /// coyote.play(frames).await?;
+    /// coyote.stop_recording()?;
+    ///
+    /// coyote.play_file("session.dglr").await?;
+    /// ```
+    pub async fn play_file(&self, path: impl AsRef<std::path::Path>) -> Result<()> {
+        let path = path.as_ref().to_owned();
+        let frames = tokio::task::spawn_blocking(move || {
+            std::fs::read(path).and_then(|bytes| parse_recording(&bytes))
+        })
+        .await
+        .unwrap_or_else(|e| Err(std::io::Error::other(e)))
+        .map_err(Error::Recording)?;
+
+        play_recording(frames, &*self.timer, |pulses| self.send_pulses(pulses)).await
+    }
+    /// Start sending pulses in the background.
+    ///
+    /// The returned [`PulseStream`] sends the pulses set using [`PulseStream::set_pulses()`]
+    /// every 100 ms (or the configured [pulse interval](Coyote3Builder::pulse_interval)), so
+    /// there is no need for a timer loop. Once it is dropped, the output is
+    /// [stopped](Self::stop). While the output is [paused](Self::pause) or during an
+    /// [emergency stop](Self::emergency_stop), nothing is sent.
+    ///
+    /// With [automatic reconnection](Coyote3Builder::auto_reconnect), the stream keeps running
+    /// while the connection is lost and resumes sending the last pulses once it is restored. An
+    /// intensity change that could not be sent yet is sent then.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn start_stream(&self) -> PulseStream {
+        let state = Arc::new(Mutex::new(StreamState::default()));
+
+        let task = tokio::spawn({
+            let coyote = self.clone();
+            let state = state.clone();
+
+            async move {
+                let interval = Interval::new(coyote.timer.clone(), coyote.pulse_interval);
+                stream_pulses(&state, interval, |pulses| coyote.send_pulses(pulses)).await;
+            }
+            .instrument(self.span.clone())
+        });
+
+        PulseStream {
+            coyote: self.clone(),
+            state,
+            task,
+        }
+    }
+    /// Stop any output immediately.
+    ///
+    /// This sets the intensity of both channels to zero and sends a silent waveform, so the output
+    /// stops right away instead of when the device notices that no more pulses are coming in.
+    ///
+    /// This also works during an [emergency stop](Self::emergency_stop).
+    pub async fn stop(&self) -> Result<()> {
+        self.write_pulses(Pulses::STOP).await
+    }
+    /// Stop any output and set the [limit](DeviceSettings::limit) of both channels to zero.
+    ///
+    /// Unlike [`stop()`](Self::stop), this also keeps the device from outputting anything if
+    /// pulses with a non-zero intensity still reach it, e.g. from another task or app. Until
+    /// [`clear_emergency_stop()`](Self::clear_emergency_stop) is called,
+    /// [`send_pulses()`](Self::send_pulses) fails with [`Error::EmergencyStopped`], which also
+    /// applies to [`play()`](Self::play) and a running [`PulseStream`].
+    ///
+    /// Both commands are sent even if the first one fails, and the first error is returned.
+    pub async fn emergency_stop(&self) -> Result<()> {
+        self.emergency.lock().unwrap().engaged = true;
+
+        let (pulses, settings) = emergency_commands(self.settings());
+        let stopped = self.write_pulses(pulses).await;
+        let limited = self.update_settings(settings).await;
+
+        stopped.and(limited)
+    }
+    /// Allow sending pulses again after an [emergency stop](Self::emergency_stop).
+    ///
+    /// This does not restore the limit, which needs to be raised again using
+    /// [`update_settings()`](Self::update_settings).
+    pub fn clear_emergency_stop(&self) {
+        self.emergency.lock().unwrap().engaged = false;
+    }
+    /// Whether an [emergency stop](Self::emergency_stop) is in effect.
+    pub fn is_emergency_stopped(&self) -> bool {
+        self.emergency.lock().unwrap().engaged
+    }
+    /// Pause the output by sending a silent waveform, remembering the last pulses sent so the
+    /// output can be [resumed](Self::resume) with them.
+    ///
+    /// The intensity is not changed. Until the output is resumed,
+    /// [`send_pulses()`](Self::send_pulses) fails with [`Error::Paused`], which also applies to
+    /// [`play()`](Self::play), while a running [`PulseStream`] skips sending. Use
+    /// [`send_pulses_forced()`](Self::send_pulses_forced) to send pulses anyway.
+    pub async fn pause(&self) -> Result<()> {
+        let pulses = self.pause.lock().unwrap().pause();
+        self.write_pulses(pulses).await
+    }
+    /// Resume the output after a [pause](Self::pause) by sending the last pulses that were sent
+    /// before or during it again.
+    ///
+    /// Their intensity change is not applied again, since the intensity was kept during the
+    /// pause. If no pulses were sent yet, a silent waveform is sent. During an
+    /// [emergency stop](Self::emergency_stop), the output is resumed but this fails with
+    /// [`Error::EmergencyStopped`].
+    pub async fn resume(&self) -> Result<()> {
+        let pulses = self.pause.lock().unwrap().resume();
+        self.send_pulses(pulses).await
+    }
+    /// Whether the output is [paused](Self::pause).
+    pub fn is_paused(&self) -> bool {
+        self.pause.lock().unwrap().paused
+    }
+    /// Update the device settings.
+    ///
+    /// Invalid settings are rejected with [`Error::InvalidSettings`]. Like
+    /// [`send_pulses()`](Self::send_pulses), this is cancel-safe.
+    pub async fn update_settings(&self, settings: DeviceSettings) -> Result<()> {
+        check_settings(&settings, self.safety_threshold)?;
+        *self.settings.lock().unwrap() = settings;
+        let settings = settings.oriented(self.swap_channels);
+        self.writer
+            .send(Command::UpdateSettings(settings), self.settings_write_type)
+            .await?;
+        self.report_dry_run(Update::Settings(settings));
+
+        Ok(())
+    }
+    /// Change the [limit](DeviceSettings::limit) and keep the other settings.
+    ///
+    /// The other settings are taken from the settings last sent with
+    /// [`update_settings()`](Self::update_settings), or those the connection started with, not
+    /// from the settings last reported by the device. So changing several settings right after
+    /// each other doesn't undo the earlier changes.
+    pub async fn set_limit(&self, limit: Stereo<u8>) -> Result<()> {
+        self.update_setting(SettingsField::Limit, limit).await
+    }
+    /// Change the [frequency balance](DeviceSettings::frequency_balance) and keep the other
+    /// settings, see [`set_limit()`](Self::set_limit).
+    pub async fn set_frequency_balance(&self, frequency_balance: Stereo<u8>) -> Result<()> {
+        self.update_setting(SettingsField::FrequencyBalance, frequency_balance)
+            .await
+    }
+    /// Change the [intensity balance](DeviceSettings::intensity_balance) and keep the other
+    /// settings, see [`set_limit()`](Self::set_limit).
+    pub async fn set_intensity_balance(&self, intensity_balance: Stereo<u8>) -> Result<()> {
+        self.update_setting(SettingsField::IntensityBalance, intensity_balance)
+            .await
+    }
+    async fn update_setting(&self, field: SettingsField, value: Stereo<u8>) -> Result<()> {
+        let mut settings = *self.settings.lock().unwrap();
+        *settings.field_mut(field) = value;
+        self.update_settings(settings).await
+    }
+    /// Reset the device settings to their [defaults](DeviceSettings::default()).
+    ///
+    /// This also resets the [limit](DeviceSettings::limit) of both channels to 70, which raises
+    /// it if it was set lower before.
+    pub async fn reset_settings(&self) -> Result<()> {
+        self.update_settings(DeviceSettings::default()).await
+    }
+    /// Update the device settings and wait until the device reports that it applied them.
+    ///
+    /// If the device does not confirm the settings within one second, this fails with
+    /// [`Error::Timeout`].
+    pub async fn update_settings_confirmed(&self, settings: DeviceSettings) -> Result<()> {
+        // subscribe first to not miss the confirmation
+        let reported = settings.oriented(self.swap_channels);
+        let mut confirmations = self
+            .peripheral
+            .notifications()
+            .await?
+            .filter(|notification| std::future::ready(reported.confirmed_by(notification)));
+
+        self.update_settings(settings).await?;
+
+        match timeout(&*self.timer, CONFIRMATION_TIMEOUT, confirmations.next()).await {
+            Some(Some(_)) => Ok(()),
+            Some(None) => Err(btleplug::Error::NotConnected.into()),
+            None => Err(Error::Timeout),
+        }
+    }
+}
+
+/// Write `pulses` as they are unless an emergency stop is in effect, see
+/// [`Coyote3::send_pulses_raw()`].
+async fn send_raw(
+    writer: &Writer,
+    emergency: &Mutex<EmergencyStop>,
+    pulses: &Pulses,
+    swap_channels: bool,
+) -> Result<()> {
+    emergency.lock().unwrap().check()?;
+
+    let command = Command::SendPulses(pulses.oriented(swap_channels));
+    writer.send(command, WriteType::WithoutResponse).await
+}
+
+/// The pulses and settings sent by [`Coyote3::emergency_stop()`], given the current `settings`.
+fn emergency_commands(settings: DeviceSettings) -> (Pulses, DeviceSettings) {
+    let settings = DeviceSettings {
+        limit: Stereo::symmetric(0),
+        ..settings
+    };
+
+    (Pulses::STOP, settings)
+}
+
+/// Send the pulses of `state` on each tick of `interval`, see [`Coyote3::start_stream()`].
+///
+/// Intensity changes are only sent once. If sending fails because the device is disconnected
+/// or the output is paused, the intensity change is kept for the next tick, so it is not lost
+/// while reconnecting. During an emergency stop, nothing is sent and intensity changes are
+/// dropped, so they don't take effect once it is cleared. The same goes for the steps of a
+/// ramp, see [`StreamState::next()`].
+async fn stream_pulses<F: Future<Output = Result<()>>>(
+    state: &Mutex<StreamState>,
+    mut interval: Interval,
+    mut send: impl FnMut(Pulses) -> F,
+) {
+    loop {
+        interval.tick().await;
+
+        let (next, ramp) = state.lock().unwrap().next();
+        let result = send(next).await;
+        let result = state.lock().unwrap().sent(next, ramp, result);
+
+        match result {
+            Ok(()) | Err(Error::Paused | Error::EmergencyStopped) => {}
+            Err(Error::Disconnected(_)) => debug!("not connected, retrying with the next pulses"),
+            Err(e) => error!(?e),
+        }
+    }
+}
+
+async fn play<F: Future<Output = Result<()>>>(
+    frames: impl Stream<Item = Pulses>,
+    mut interval: Interval,
+    mut send: impl FnMut(Pulses) -> F,
+) -> Result<()> {
+    let mut frames = std::pin::pin!(frames);
+
+    while let Some(pulses) = frames.next().await {
+        interval.tick().await;
+        send(pulses).await?;
+    }
+
+    Ok(())
+}
+
+/// Two handles are equal if they are connected to the same peripheral, see
+/// [`Coyote3::id()`].
+///
+/// This holds for clones as well as for handles that connected separately, e.g. after a
+/// reconnection, so handles can be used as keys in a `HashMap`.
+impl PartialEq for Coyote3 {
+    fn eq(&self, other: &Self) -> bool {
+        self.id() == other.id()
+    }
+}
+
+impl Eq for Coyote3 {}
+
+/// Hashes the [`id()`](Coyote3::id) of the peripheral, consistent with `PartialEq`.
+impl std::hash::Hash for Coyote3 {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id().hash(state);
+    }
+}
+
+impl crate::core::Sealed for Coyote3 {}
+
+impl Device for Coyote3 {
+    type State = State;
+    type Pulses = Pulses;
+    type Settings = DeviceSettings;
+
+    fn state(&self) -> impl StateSignal<State> + Send + use<> {
+        self.state.clone()
+    }
+    fn send_pulses(&self, pulses: Pulses) -> impl Future<Output = Result<()>> + Send {
+        Coyote3::send_pulses(self, pulses)
+    }
+    fn update_settings(&self, settings: DeviceSettings) -> impl Future<Output = Result<()>> + Send {
+        Coyote3::update_settings(self, settings)
+    }
+    fn stop(&self) -> impl Future<Output = Result<()>> + Send {
+        Coyote3::stop(self)
+    }
+    fn disconnect(&self) -> impl Future<Output = Result<()>> + Send {
+        Coyote3::disconnect(self)
+    }
+}
+
+/// Sends pulses to a Coyote 3 every 100 ms in the background.
+///
+/// This is created by [`Coyote3::start_stream()`]. Dropping it stops the background task and
+/// the output of the device.
+#[derive(Debug)]
+pub struct PulseStream {
+    coyote: Coyote3,
+    state: Arc<Mutex<StreamState>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl PulseStream {
+    /// Set the pulses that are sent from now on.
+    ///
+    /// The intensity change of `pulses` is only sent once, the following frames repeat the
+    /// waveform without changing the intensity again.
+    pub fn set_pulses(&self, pulses: Pulses) {
+        self.state.lock().unwrap().pulses = pulses;
+    }
+    /// Smoothly change the stimulation intensity to `target` over `duration`.
+    ///
+    /// Starting at the current [intensity](Coyote3::intensity), the stream sets the intensity
+    /// along with each of the frames it sends during `duration`. This resolves once the frame
+    /// reaching `target` was sent. Each step is an absolute change, so changes made using the
+    /// “shoulder” switches of the coyote while ramping are overwritten by the next step. Once
+    /// the ramp has completed, they work as usual again.
+    ///
+    /// A step is only taken once it was sent: while the device is disconnected or the output is
+    /// [paused](Coyote3::pause), the ramp waits. An intensity change set using
+    /// [`set_pulses()`](Self::set_pulses) is sent before the next step, which delays the ramp by
+    /// one frame. If a step can't be sent for any other reason, e.g. during an
+    /// [emergency stop](Coyote3::emergency_stop), the ramp ends and this fails with the error.
+    /// Starting another ramp replaces this one, which then resolves right away.
+    ///
+    /// This is cancel-safe: if the returned future is dropped, the ramp ends and the intensity
+    /// stays at the level that was reached so far.
+    pub async fn ramp_intensity(&self, target: Stereo<u8>, duration: Duration) -> Result<()> {
+        let start = self.coyote.state.get().intensity;
+        let steps = (duration.as_millis() / self.coyote.pulse_interval.as_millis()).max(1) as u32;
+        let (done, result) = futures::channel::oneshot::channel();
+
+        self.state.lock().unwrap().ramp = Some(Ramp {
+            start,
+            target,
+            taken: 0,
+            steps,
+            done,
+        });
+
+        // the ramp was replaced by another one if the sender is gone
+        result.await.unwrap_or(Ok(()))
+    }
+}
+
+/// What the task of a [`PulseStream`] sends.
+#[derive(Debug)]
+struct StreamState {
+    pulses: Pulses,
+    /// The ramp in progress, see [`PulseStream::ramp_intensity()`].
+    ramp: Option<Ramp>,
+}
+
+impl Default for StreamState {
+    fn default() -> Self {
+        Self {
+            pulses: Pulses::silent(),
+            ramp: None,
+        }
+    }
+}
+
+/// A ramp of the intensity from `start` to `target` in `steps` steps, one per frame sent.
+#[derive(Debug)]
+struct Ramp {
+    start: Stereo<u8>,
+    target: Stereo<u8>,
+    /// The number of steps sent so far.
+    taken: u32,
+    steps: u32,
+    /// Reports the end of the ramp to [`PulseStream::ramp_intensity()`].
+    done: futures::channel::oneshot::Sender<Result<()>>,
+}
+
+impl StreamState {
+    /// The pulses to send next, along with the ramp if they take its next step.
+    ///
+    /// A pending intensity change of the pulses is sent before the next step, and a ramp whose
+    /// future was dropped is ended without taking another step.
+    fn next(&mut self) -> (Pulses, Option<Ramp>) {
+        let mut next = self.pulses;
+        let ramp = self.ramp.take_if(|ramp| !ramp.done.is_canceled());
+        match ramp {
+            Some(ramp) if next.intensity == Pulses::silent().intensity => {
+                let step = ramp_step(ramp.start, ramp.target, ramp.taken + 1, ramp.steps);
+                next.intensity = step.map(IntensityChange::AbsoluteChange);
+                (next, Some(ramp))
+            }
+            ramp => {
+                self.ramp = ramp;
+                (next, None)
+            }
+        }
+    }
+    /// Update the state after sending `sent`, which took a step of `ramp` if set, returning the
+    /// `result` of sending unless it was handed to the ramp.
+    fn sent(&mut self, sent: Pulses, ramp: Option<Ramp>, result: Result<()>) -> Result<()> {
+        // keep the intensity change or the step for the next tick
+        if let Err(Error::Disconnected(_) | Error::Paused) = result {
+            self.ramp = self.ramp.take().or(ramp);
+            return result;
+        }
+
+        let Some(mut ramp) = ramp else {
+            // only apply intensity changes once, unless a new one was set in the meantime
+            if self.pulses.intensity == sent.intensity {
+                self.pulses.intensity = Pulses::silent().intensity;
+            }
+            return result;
+        };
+        if let Err(e) = result {
+            let _ = ramp.done.send(Err(e));
+            return Ok(());
+        }
+
+        ramp.taken += 1;
+        if ramp.taken == ramp.steps {
+            let _ = ramp.done.send(Ok(()));
+        } else if self.ramp.is_none() {
+            // unless it was replaced while sending
+            self.ramp = Some(ramp);
+        }
+        Ok(())
+    }
+}
+
+/// The intensity after `step` of `steps` steps from `start` to `target`.
+fn ramp_step(start: Stereo<u8>, target: Stereo<u8>, step: u32, steps: u32) -> Stereo<u8> {
+    start.zip_with(target, |start, target| {
+        let (start, target) = (start as i64, target as i64);
+        (start + (target - start) * step as i64 / steps as i64) as u8
+    })
+}
+
+impl Drop for PulseStream {
+    fn drop(&mut self) {
+        self.task.abort();
+
+        let coyote = self.coyote.clone();
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move {
+                    if let Err(e) = coyote.stop().await {
+                        error!(?e, "failed to stop output");
+                    }
+                });
+            }
+            Err(_) => error!("pulse stream dropped outside of a Tokio runtime, output not stopped"),
+        }
+    }
+}
+
+/// The client-side estimate of the intensity, see [`Coyote3::projected_intensity()`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Projection {
+    /// The intensity last reported by the device.
+    reported: Stereo<u8>,
+    projected: Stereo<u8>,
+}
+
+impl Projection {
+    fn new(reported: Stereo<u8>) -> Self {
+        Self {
+            reported,
+            projected: reported,
+        }
+    }
+    /// Start over from the intensity reported by the device if it changed.
+    fn reconcile(&mut self, reported: Stereo<u8>) {
+        if reported != self.reported {
+            *self = Self::new(reported);
+        }
+    }
+    /// Limit the intensity change of `pulses` so the projected intensity stays within `limit`,
+    /// and project the result.
+    fn apply(&mut self, reported: Stereo<u8>, pulses: Pulses, limit: Stereo<u8>) -> Pulses {
+        self.reconcile(reported);
+
+        let limited = pulses.limited(self.projected, limit);
+        if limited.intensity != pulses.intensity {
+            debug!(
+                requested = ?pulses.intensity,
+                limited = ?limited.intensity,
+                "intensity change would overshoot"
+            );
+        }
+
+        self.projected = self
+            .projected
+            .zip(limited.intensity)
+            .zip_with(limit, |(current, change), limit| {
+                change.applied_to(current, limit)
+            });
+        limited
+    }
+    /// Go back to `previous` after the pulses that led from it to `applied` could not be sent,
+    /// unless the projection changed since.
+    fn undo(&mut self, applied: Projection, previous: Projection) {
+        if *self == applied {
+            *self = previous;
+        }
+    }
+}
+
+/// The current state of the Coyote 3. This can be obtained by calling [`Coyote3::state()`].
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct State {
+    /// The current battery charge in percent.
+    pub battery: u8,
+    /// The current stimulation intensity.
+    pub intensity: Stereo<u8>,
+    /// The current device settings.
+    pub settings: DeviceSettings,
+    /// The signal strength in dBm.
+    ///
+    /// This is `None` on platforms that don't report the signal strength of connected devices.
+    /// To avoid constant updates, changes of less than 5 dBm are ignored.
+    pub rssi: Option<i16>,
+    /// Whether the device is currently connected.
+    ///
+    /// With [automatic reconnection](Coyote3Builder::auto_reconnect), this is `false` while the
+    /// connection is being restored.
+    pub connected: bool,
+}
+
+/// A compact summary like `A:20 B:0 batt:85% limit A70/B70`, followed by `disconnected` if the
+/// device is not connected.
+impl std::fmt::Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "A:{} B:{} batt:{}% limit A{}/B{}",
+            self.intensity.a,
+            self.intensity.b,
+            self.battery,
+            self.settings.limit.a,
+            self.settings.limit.b,
+        )?;
+        if !self.connected {
+            write!(f, " disconnected")?;
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// Apply an update, returning whether anything has changed.
+    ///
+    /// Updates that repeat the current values are skipped here, so the state is only copied
+    /// into the signal when it actually changed.
+    fn apply(&mut self, update: Update) -> bool {
+        let notification = match update {
+            Update::Notification(notification) => notification,
+            Update::Rssi(rssi) => {
+                if !rssi_changed(self.rssi, rssi) {
+                    return false;
+                }
+                self.rssi = rssi;
+                return true;
+            }
+            Update::Connected(connected) => return replace(&mut self.connected, connected),
+            Update::Intensity(intensity) => return replace(&mut self.intensity, intensity),
+            Update::Settings(settings) => return replace(&mut self.settings, settings),
+        };
+
+        debug!(?notification);
+        match notification.uuid {
+            NOTIFY_CHARACTERISTIC_UUID => {
+                if !ParsedNotification::is_known(&notification.value) {
+                    debug!("received unknown notification");
+                    return false;
+                }
+
+                match parse_notification(&notification.value) {
+                    Ok(ParsedNotification::IntensityChange {
+                        serial: _,
+                        intensity,
+                    }) => replace(&mut self.intensity, intensity),
+                    Ok(ParsedNotification::DeviceSettingsChange(parameters)) => {
+                        replace(&mut self.settings, parameters)
+                    }
+                    Err(e) => {
+                        error!(?e);
+                        false
+                    }
+                }
+            }
+            BATTERY_CHARACTERISTIC_UUID => match parse_battery(&notification.value) {
+                Ok(battery) => replace(&mut self.battery, battery),
+                Err(e) => {
+                    error!(?e);
+                    false
+                }
+            },
+            uuid => {
+                debug!("received notification for unknown characteristic {uuid}");
+                false
+            }
+        }
+    }
+}
+
+/// An event reported by the Coyote 3. These can be obtained by calling [`Coyote3::events()`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// The stimulation intensity has changed.
+    IntensityChanged {
+        /// What caused the change.
+        source: IntensitySource,
+        /// Whether the change was most likely caused by this client, see [`IntensityOrigin`].
+        origin: IntensityOrigin,
+        /// The new stimulation intensity.
+        intensity: Stereo<u8>,
+    },
+    /// The device reported settings that differ from the ones this client set last, e.g.
+    /// because another app connected to the device and changed them.
+    ///
+    /// Settings confirmed by the device after [`Coyote3::update_settings()`] do not cause this
+    /// event. If settings are updated again before the device confirmed the previous ones, the
+    /// late confirmation may be reported as drift.
+    SettingsDrift {
+        /// The settings this client set last.
+        intended: DeviceSettings,
+        /// The settings reported by the device.
+        reported: DeviceSettings,
+    },
+    /// The device sent a notification of a type that is not known to this library, e.g. one
+    /// added by a newer firmware.
+    Unknown {
+        /// The first byte, identifying the type of the notification.
+        magic: u8,
+        /// The remaining bytes.
+        payload: Vec<u8>,
+    },
+}
+
+impl Event {
+    /// Parse `notification`, given the `origin` of an intensity change reported with a serial
+    /// number and the `intended` settings.
+    fn from_notification(
+        notification: &ValueNotification,
+        origin: impl FnOnce(u8) -> IntensityOrigin,
+        intended: DeviceSettings,
+    ) -> Option<Result<Self>> {
+        if notification.uuid != NOTIFY_CHARACTERISTIC_UUID {
+            return None;
+        }
+        if let Some((&magic, payload)) = notification.value.split_first()
+            && !ParsedNotification::MAGIC.contains(&magic)
+        {
+            return Some(Ok(Event::Unknown {
+                magic,
+                payload: payload.to_vec(),
+            }));
+        }
+
+        match parse_notification(&notification.value) {
+            Ok(ParsedNotification::IntensityChange { serial, intensity }) => {
+                Some(Ok(Event::IntensityChanged {
+                    source: IntensitySource::from_serial(serial),
+                    origin: origin(serial),
+                    intensity,
+                }))
+            }
+            Ok(ParsedNotification::DeviceSettingsChange(reported)) => {
+                (reported != intended).then_some(Ok(Event::SettingsDrift { intended, reported }))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// What caused an intensity change.
+///
+/// The Coyote 3 reports each intensity change together with a serial number. If the change was
+/// caused by a command that carried a serial number (1 to 15), the same serial number is
+/// reported back. Changes that were made on the device itself, e.g. using the “shoulder”
+/// switches, are reported with the serial number 0.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntensitySource {
+    /// The intensity was changed on the device itself.
+    Device,
+    /// The intensity was changed by the command with the given serial number.
+    Command {
+        /// The serial number of the command.
+        serial: u8,
+    },
+}
+
+impl IntensitySource {
+    fn from_serial(serial: u8) -> Self {
+        match serial {
+            0 => IntensitySource::Device,
+            serial => IntensitySource::Command { serial },
+        }
+    }
+}
+
+/// Whether an intensity change was caused by this client, e.g. to not feed changes back to the
+/// device when mirroring the intensity to a slider.
+///
+/// [`Coyote3::send_pulses()`] tags each intensity change with one of the serial numbers 1 to
+/// 15 in turn, which the device reports back along with the resulting intensity. A reported
+/// change counts as local if its serial number was handed out by this client and not reported
+/// back since, as seen by the [event stream](Coyote3::events) it is reported on. So each
+/// command of this client is counted as local at most once, and a later change reported with
+/// the same serial number, e.g. by another program, counts as remote again. This is a heuristic
+/// with some limits:
+///
+/// - Intensity changes are only reported when the intensity actually changes. A command that
+///   has no effect, e.g. because the intensity already is at the limit, is not reported at all,
+///   so its serial number stays outstanding until it is reported with another change.
+/// - Commands sent before the event stream was created are not outstanding for it, so their
+///   changes count as remote.
+/// - A change made on the device while a command is in flight is reported as remote, even though
+///   the intensity reported with it might include the change of the command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntensityOrigin {
+    /// The change was caused by a command sent by this client.
+    Local,
+    /// The change was made on the device itself or by a command of someone else.
+    Remote,
+}
+
+/// Hands out the serial numbers of the commands that change the intensity and counts how often
+/// each one was handed out, see [`IntensityOrigin`].
+#[derive(Debug, Default)]
+struct Serials {
+    /// The serial number handed out last, 0 if none was handed out yet.
+    last: u8,
+    /// How often each serial number was handed out, wrapping around.
+    issued: [u32; 16],
+}
+
+impl Serials {
+    /// Hand out the next serial number, cycling through 1 to 15.
+    fn issue(&mut self) -> u8 {
+        self.last = self.last % 15 + 1;
+        let issued = &mut self.issued[usize::from(self.last)];
+        *issued = issued.wrapping_add(1);
+        self.last
+    }
+}
+
+/// The serial numbers handed out by [`Serials`] that were not reported back yet, as seen by a
+/// single [event stream](Coyote3::events).
+#[derive(Debug)]
+struct Outstanding {
+    /// How often each serial number was handed out when it was last reported back.
+    reported: [u32; 16],
+}
+
+impl Outstanding {
+    /// Start tracking the serial numbers handed out from now on.
+    fn new(serials: &Serials) -> Self {
+        Self {
+            reported: serials.issued,
+        }
+    }
+    /// The origin of an intensity change reported with `serial`, which is no longer outstanding
+    /// afterwards.
+    fn report(&mut self, serials: &Serials, serial: u8) -> IntensityOrigin {
+        let index = usize::from(serial);
+        if serial == 0 || index >= serials.issued.len() {
+            return IntensityOrigin::Remote;
+        }
+
+        let reported = std::mem::replace(&mut self.reported[index], serials.issued[index]);
+        if reported == serials.issued[index] {
+            IntensityOrigin::Remote
+        } else {
+            IntensityOrigin::Local
+        }
+    }
+}
+
+/// Whether an emergency stop is in effect, see [`Coyote3::emergency_stop()`].
+#[derive(Debug, Default)]
+struct EmergencyStop {
+    engaged: bool,
+}
+
+impl EmergencyStop {
+    /// Fail if pulses can't be sent because of the emergency stop.
+    fn check(&self) -> Result<()> {
+        if self.engaged {
+            Err(Error::EmergencyStopped)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Whether the output is paused and the pulses to resume it with, see [`Coyote3::pause()`].
+#[derive(Debug, Default)]
+struct Pause {
+    paused: bool,
+    /// The last pulses sent, without their intensity change.
+    last: Option<Pulses>,
+}
+
+impl Pause {
+    /// Fail if pulses can't be sent because the output is paused.
+    fn check(&self) -> Result<()> {
+        if self.paused {
+            Err(Error::Paused)
+        } else {
+            Ok(())
+        }
+    }
+    /// Remember `pulses` to resume with.
+    fn record(&mut self, pulses: Pulses) {
+        self.last = Some(Pulses {
+            intensity: Pulses::silent().intensity,
+            ..pulses
+        });
+    }
+    /// Pause, returning the pulses to send instead.
+    fn pause(&mut self) -> Pulses {
+        self.paused = true;
+        Pulses::silent()
+    }
+    /// Resume, returning the pulses to resume with.
+    fn resume(&mut self) -> Pulses {
+        self.paused = false;
+        self.last.unwrap_or(Pulses::silent())
+    }
+}
+
+/// The times pulses were sent at, see [`Coyote3::last_sent()`] and [`Coyote3::jitter()`].
+#[derive(Debug, Default)]
+struct SendTimes {
+    last: Option<Instant>,
+    /// The time between the last pulses sent, oldest first.
+    intervals: VecDeque<Duration>,
+}
+
+impl SendTimes {
+    fn record(&mut self, at: Instant) {
+        if let Some(last) = self.last {
+            if self.intervals.len() == JITTER_WINDOW {
+                self.intervals.pop_front();
+            }
+            self.intervals.push_back(at.saturating_duration_since(last));
+        }
+        self.last = Some(at);
+    }
+    /// The mean absolute deviation of the intervals from `expected`.
+    fn jitter(&self, expected: Duration) -> Option<Duration> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+
+        let deviation = self
+            .intervals
+            .iter()
+            .map(|&interval| interval.abs_diff(expected))
+            .sum::<Duration>();
+        Some(deviation / self.intervals.len() as u32)
+    }
+}
+
+/// A battery threshold crossing, see [`Coyote3::battery_events()`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatteryEvent {
+    /// The battery charge dropped to or below the low threshold.
+    Low,
+    /// The battery charge dropped to or below the critical threshold.
+    Critical,
+    /// The battery charge rose above the low threshold again.
+    Recovered,
+}
+
+/// Tracks the battery thresholds for [`Coyote3::battery_events()`].
+struct BatteryMonitor {
+    low: u8,
+    critical: u8,
+    level: BatteryLevel,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BatteryLevel {
+    Fine,
+    Low,
+    Critical,
+}
+
+impl BatteryMonitor {
+    fn new(low: u8, critical: u8) -> Self {
+        Self {
+            low,
+            critical,
+            level: BatteryLevel::Fine,
+        }
+    }
+    /// Update the battery charge, returning the event if a threshold was crossed.
+    fn update(&mut self, battery: u8) -> Option<BatteryEvent> {
+        let rose_above = |threshold: u8| battery > threshold.saturating_add(BATTERY_HYSTERESIS);
+
+        let (level, event) = match self.level {
+            BatteryLevel::Critical if battery <= self.critical => (BatteryLevel::Critical, None),
+            _ if battery <= self.critical => (BatteryLevel::Critical, Some(BatteryEvent::Critical)),
+            BatteryLevel::Fine if battery <= self.low => {
+                (BatteryLevel::Low, Some(BatteryEvent::Low))
+            }
+            BatteryLevel::Fine => (BatteryLevel::Fine, None),
+            _ if rose_above(self.low) => (BatteryLevel::Fine, Some(BatteryEvent::Recovered)),
+            // not worth an event, but dropping to critical again is reported
+            BatteryLevel::Critical if rose_above(self.critical) => (BatteryLevel::Low, None),
+            level => (level, None),
+        };
+
+        self.level = level;
+        event
+    }
+}
+
+/// The device settings of the Coyote 3.
+///
+/// Prefer [`DeviceSettings::new()`] over a struct literal, since it checks that the settings
+/// are supported by the device.
+#[derive(Clone, Copy, Debug, PartialEq, SmartDefault, binrw::BinRead, binrw::BinWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[brw(big)]
+pub struct DeviceSettings {
+    /// The maximum intensity limit, from 0 to [`MAX_LIMIT`](Self::MAX_LIMIT).
+    ///
+    /// <div class="warning">It is very important that a user can set this to appropriate levels.</div>
+    #[default((70, 70).into())]
+    pub limit: Stereo<u8>,
+
+    /// The “frequency balance” parameter affects the perceived intensity at different frequencies.
+    /// The device accepts the whole range from 0 to 255.
+    ///
+    /// The official app explains it as following:
+    ///
+    /// > This parameter controls the relative intensity of waveforms at different frequencies,
+    /// > under a fixed channel intensity. Higher values increase the throbbing sensation of
+    /// > low-frequency waveforms.
+    #[default((160, 160).into())]
+    pub frequency_balance: Stereo<u8>,
+
+    /// The “intensity balance” parameter affects the pulse width of the waveform.
+    /// Whether this parameter actually influences the waveform is currently questionable.
+    /// The device accepts the whole range from 0 to 255.
+    ///
+    /// The official app explains it as following:
+    ///
+    /// > This parameter controls the relative intensity of waveforms at different frequencies,
+    /// > under a fixed channel intensity. Higher values increase the perceived stimulation of
+    /// > low-frequency waveforms.
+    #[default((0, 0).into())]
+    pub intensity_balance: Stereo<u8>,
+}
+
+impl DeviceSettings {
+    /// The maximum intensity limit supported by the device.
+    pub const MAX_LIMIT: u8 = 200;
+    /// A conservative intensity limit for getting started, see [`Coyote3::connect_safe()`].
+    pub const SAFE_LIMIT: u8 = 20;
+
+    /// Create settings after checking that they are supported by the device, see
+    /// [`validate()`](Self::validate).
+    ///
+    /// ```
+    /// # use dungeonctl::{Stereo, coyote3::{DeviceSettings, SettingsError}};
+    /// let balance = Stereo::symmetric(160);
+    /// let settings = DeviceSettings::new(Stereo { a: 50, b: 30 }, balance, Stereo::symmetric(0))?;
+    ///
+    /// assert!(DeviceSettings::new(Stereo::symmetric(250), balance, Stereo::symmetric(0)).is_err());
+    /// # Ok::<(), SettingsError>(())
+    /// ```
+    pub fn new(
+        limit: Stereo<u8>,
+        frequency_balance: Stereo<u8>,
+        intensity_balance: Stereo<u8>,
+    ) -> std::result::Result<Self, SettingsError> {
+        let settings = Self {
+            limit,
+            frequency_balance,
+            intensity_balance,
+        };
+        settings.validate()?;
+
+        Ok(settings)
+    }
+    /// The default settings with the limit of both channels lowered to
+    /// [`SAFE_LIMIT`](Self::SAFE_LIMIT).
+    pub fn safe() -> Self {
+        Self {
+            limit: Stereo::symmetric(Self::SAFE_LIMIT),
+            ..Default::default()
+        }
+    }
+
+    /// Check that the settings are supported by the device.
+    ///
+    /// Only the limit can be out of range, the balance parameters accept any value.
+    pub fn validate(&self) -> std::result::Result<(), SettingsError> {
+        for limit in self.limit {
+            if limit > Self::MAX_LIMIT {
+                return Err(SettingsError::LimitOutOfRange(limit));
+            }
+        }
+
+        Ok(())
+    }
+    fn field_mut(&mut self, field: SettingsField) -> &mut Stereo<u8> {
+        match field {
+            SettingsField::Limit => &mut self.limit,
+            SettingsField::FrequencyBalance => &mut self.frequency_balance,
+            SettingsField::IntensityBalance => &mut self.intensity_balance,
+        }
+    }
+    /// Whether `notification` reports that the device applied these settings.
+    fn confirmed_by(&self, notification: &ValueNotification) -> bool {
+        notification.uuid == NOTIFY_CHARACTERISTIC_UUID
+            && matches!(
+                parse_notification(&notification.value),
+                Ok(ParsedNotification::DeviceSettingsChange(reported)) if reported == *self
+            )
+    }
+}
+
+/// A single field of the [`DeviceSettings`], see [`Coyote3::set_limit()`].
+#[derive(Clone, Copy, Debug)]
+enum SettingsField {
+    Limit,
+    FrequencyBalance,
+    IntensityBalance,
+}
+
+/// A compact summary like `limit A70/B70 freq-balance A160/B160 int-balance A0/B0`.
+impl std::fmt::Display for DeviceSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Self {
+            limit,
+            frequency_balance,
+            intensity_balance,
+        } = self;
+
+        write!(
+            f,
+            "limit A{}/B{} freq-balance A{}/B{} int-balance A{}/B{}",
+            limit.a,
+            limit.b,
+            frequency_balance.a,
+            frequency_balance.b,
+            intensity_balance.a,
+            intensity_balance.b,
+        )
+    }
+}
+
+fn check_settings(settings: &DeviceSettings, safety_threshold: Option<u8>) -> Result<()> {
+    settings.validate()?;
+
+    if let Some(threshold) = safety_threshold
+        && settings.limit.into_iter().any(|limit| limit > threshold)
+    {
+        warn!(limit = ?settings.limit, threshold, "intensity limit exceeds safety threshold");
+    }
+
+    Ok(())
+}
+
+/// The error returned by [`DeviceSettings::validate()`] for settings that are not supported by
+/// the device.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SettingsError {
+    /// The intensity limit is above 200.
+    LimitOutOfRange(u8),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingsError::LimitOutOfRange(limit) => {
+                write!(f, "intensity limit {limit} is above 200")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+/// The pulse data that is expected to be sent every 100 ms to the coyote.
+#[derive(Clone, Copy, Debug, PartialEq, binrw::BinWrite)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[bw(big, import(serial: u8))]
+pub struct Pulses {
+    /// This field is used to change the stimulation intensity per channel.
+    ///
+    /// Note that relative changes should be preferred in many cases over absolute changes since
+    /// absolute changes will overwrite any intensity changes that were made using the hardware
+    /// “shoulder” switches of the coyote, basically rendering them useless.
+    #[bw(map = |intensity| (
+        (serial << 4) | (intensity.a.mode() << 2) | intensity.b.mode(),
+        intensity.a.value(),
+        intensity.b.value(),
+    ))]
+    pub intensity: Stereo<IntensityChange>,
+
+    /// The actual waveform data.
+    ///
+    /// This is an array of 4 pulses of 25 ms length each, where each pulse contains the frequency
+    /// and relative amplitude for each channel.
+    #[bw(map = Self::convert_pulses)]
+    pub pulses: [Stereo<Pulse>; 4],
+}
+
+impl Pulses {
+    const STOP: Pulses = Pulses {
+        intensity: Stereo {
+            a: IntensityChange::AbsoluteChange(0),
+            b: IntensityChange::AbsoluteChange(0),
+        },
+        ..Pulses::silent()
+    };
+
+    /// The length of the command that sends pulses to the device, see
+    /// [`write_into()`](Self::write_into).
+    pub const ENCODED_LEN: usize = 20;
+
+    /// Pulses without any output that don't change the intensity.
+    pub const fn silent() -> Self {
+        Self::uniform(
+            Stereo {
+                a: IntensityChange::DoNotChange,
+                b: IntensityChange::DoNotChange,
+            },
+            Stereo {
+                a: Pulse::silent(),
+                b: Pulse::silent(),
+            },
+        )
+    }
+    /// Pulses that repeat the same `pulse` for all four 25 ms slots.
+    ///
+    /// ```
+    /// # use dungeonctl::{Stereo, coyote3::{Amplitude, Frequency, IntensityChange, Pulse, Pulses}};
+    /// let pulses = Pulses::uniform(
+    ///     Stereo::symmetric(IntensityChange::DoNotChange),
+    ///     Stereo {
+    ///         a: Pulse::new(Frequency::hz(100)?, Amplitude::from(50)),
+    ///         b: Pulse::silent(),
+    ///     },
+    /// );
+    ///
+    /// assert_eq!(pulses.pulses.map(|p| p.a.intensity.value()), [50; 4]);
+    /// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+    /// ```
+    pub const fn uniform(intensity: Stereo<IntensityChange>, pulse: Stereo<Pulse>) -> Self {
+        Pulses {
+            intensity,
+            pulses: [pulse; 4],
+        }
+    }
+
+    /// Encode the command that sends these pulses into `buf`, returning the number of bytes
+    /// written, which is always [`Pulses::ENCODED_LEN`].
+    ///
+    /// The bytes are the same as returned by [`encode_send_pulses()`]. If `buf` is shorter than
+    /// that, this fails with [`Error::BufferTooSmall`] and `buf` is left untouched. This never
+    /// allocates, not even when it fails.
+    ///
+    /// ```
+    /// # use dungeonctl::coyote3::Pulses;
+    /// let mut buf = [0; Pulses::ENCODED_LEN];
+    /// let len = Pulses::silent().write_into(&mut buf)?;
+    ///
+    /// assert_eq!(buf[..len][0], 0xB0);
+    /// assert!(matches!(
+    ///     Pulses::silent().write_into(&mut [0; 8]),
+    ///     Err(dungeonctl::Error::BufferTooSmall { needed: Pulses::ENCODED_LEN })
+    /// ));
+    /// # Ok::<(), dungeonctl::Error>(())
+    /// ```
+    pub fn write_into(&self, buf: &mut [u8]) -> Result<usize> {
+        let encoded = encode_send_pulses(self);
+        let Some(buf) = buf.get_mut(..encoded.len()) else {
+            return Err(Error::BufferTooSmall {
+                needed: encoded.len(),
+            });
+        };
+
+        buf.copy_from_slice(&encoded);
+        Ok(encoded.len())
+    }
+
+    /// Limit the intensity change so the intensity, starting at `current`, stays between zero and
+    /// `limit`.
+    fn limited(mut self, current: Stereo<u8>, limit: Stereo<u8>) -> Self {
+        self.intensity = self
+            .intensity
+            .zip(current)
+            .zip_with(limit, |(change, current), limit| {
+                change.limited(current, limit)
+            });
+        self
+    }
+    /// Silence the channels that are not `enabled` and set their intensity to zero.
+    fn muted(mut self, enabled: Stereo<bool>) -> Self {
+        for (channel, enabled) in [Channel::A, Channel::B].into_iter().zip(enabled) {
+            if enabled {
+                continue;
+            }
+
+            *self.intensity.get_mut(channel) = IntensityChange::AbsoluteChange(0);
+            for pulse in &mut self.pulses {
+                *pulse.get_mut(channel) = Pulse::silent();
+            }
+        }
+        self
+    }
+    /// Check the output of each channel at `intensity` against `limit`, see [`LimitCheck`].
+    fn checked_output(
+        mut self,
+        intensity: Stereo<u8>,
+        limit: Stereo<u8>,
+        check: LimitCheck,
+    ) -> Result<Self> {
+        for channel in [Channel::A, Channel::B] {
+            let (intensity, limit) = (*intensity.get(channel) as u32, *limit.get(channel) as u32);
+            let amplitude = self
+                .pulses
+                .iter()
+                .map(|pulse| pulse.get(channel).intensity.value() as u32)
+                .max()
+                .unwrap_or(0);
+
+            let output = intensity * amplitude / 100;
+            if output <= limit {
+                continue;
+            }
+
+            match check {
+                LimitCheck::Clamp => {
+                    let max = Amplitude::from((limit * 100 / intensity) as u8);
+                    for pulse in &mut self.pulses {
+                        let pulse = pulse.get_mut(channel);
+                        pulse.intensity = pulse.intensity.min(max);
+                    }
+                }
+                LimitCheck::Reject => {
+                    return Err(Error::LimitExceeded {
+                        channel,
+                        output: output as u8,
+                        limit: limit as u8,
+                    });
+                }
+            }
+        }
+
+        Ok(self)
+    }
+    fn convert_pulses(pulses: &[Stereo<Pulse>; 4]) -> [[u8; 4]; 4] {
+        let wire = pulses.map(|p| p.to_wire());
+        std::array::from_fn(|byte| wire.map(|pulse| pulse[byte]))
+    }
+    /// The inverse of [`Pulses::convert_pulses()`], rejecting values the device doesn't support.
+    fn parse_pulses(wire: [[u8; 4]; 4]) -> std::result::Result<[Stereo<Pulse>; 4], PulseError> {
+        let [frequency_a, amplitude_a, frequency_b, amplitude_b] = wire;
+        let pulse = |frequency: u8, amplitude: u8| {
+            Ok(Pulse::new(
+                Frequency::from_compressed(frequency)?,
+                Amplitude::new(amplitude)?,
+            ))
+        };
+
+        let mut pulses = [Stereo::symmetric(Pulse::silent()); 4];
+        for (i, p) in pulses.iter_mut().enumerate() {
+            *p = Stereo {
+                a: pulse(frequency_a[i], amplitude_a[i])?,
+                b: pulse(frequency_b[i], amplitude_b[i])?,
+            };
+        }
+        Ok(pulses)
+    }
+}
+
+/// A single frequency-intensity set representing 25 ms of a waveform for a single channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pulse {
+    /// The frequency of the waveform.
+    pub frequency: Frequency,
+    /// The pulse amplitude.
+    pub intensity: Amplitude,
+}
+
+impl Pulse {
+    /// Create a pulse.
+    pub const fn new(frequency: Frequency, intensity: Amplitude) -> Self {
+        Self {
+            frequency,
+            intensity,
+        }
+    }
+    /// A pulse without any output.
+    pub const fn silent() -> Self {
+        Self::new(Frequency::OFF, Amplitude::ZERO)
+    }
+    /// The frequency as the compressed value that is sent to the device.
+    ///
+    /// See [`compress_frequency()`].
+    pub fn compressed_frequency(&self) -> u8 {
+        self.frequency.compressed()
+    }
+    /// Interpolate linearly between this pulse at `t = 0.0` and `other` at `t = 1.0`.
+    ///
+    /// The frequency is interpolated in the period of the waveform, which is what the device
+    /// works with. If one of the pulses is [off](Frequency::OFF), the frequency of the other one
+    /// is used, so only the amplitude fades. `t` is clamped to the range of 0.0 to 1.0.
+    ///
+    /// ```
+    /// # use dungeonctl::coyote3::{Amplitude, Frequency, Pulse};
+    /// let from = Pulse::new(Frequency::hz(100)?, Amplitude::ZERO);
+    /// let to = Pulse::new(Frequency::hz(50)?, Amplitude::MAX);
+    ///
+    /// let middle = from.lerp(&to, 0.5);
+    /// assert_eq!(middle.frequency, Frequency::period_ms(15.0)?);
+    /// assert_eq!(middle.intensity, Amplitude::new(50)?);
+    /// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+    /// ```
+    pub fn lerp(&self, other: &Pulse, t: f32) -> Pulse {
+        let t = t.clamp(0.0, 1.0);
+        let lerp = |from: f32, to: f32| from + (to - from) * t;
+
+        let frequency = match (self.frequency, other.frequency) {
+            (Frequency::OFF, frequency) | (frequency, Frequency::OFF) => frequency,
+            (from, to) => Frequency(
+                compress_period_ms(lerp(
+                    compressed_period_ms(from.0),
+                    compressed_period_ms(to.0),
+                ))
+                .round() as u8,
+            ),
+        };
+        let intensity = lerp(
+            self.intensity.value() as f32,
+            other.intensity.value() as f32,
+        );
+
+        Pulse::new(frequency, Amplitude::from(intensity.round() as u8))
+    }
+}
+
+impl Stereo<Pulse> {
+    /// The bytes sent to the device for this pair of pulses: the compressed frequency and the
+    /// amplitude of channel A, followed by those of channel B.
+    ///
+    /// Within a [`Pulses`] packet, the bytes of the four pulses are grouped by their position
+    /// instead: the four frequencies of channel A, followed by its four amplitudes and then the
+    /// same for channel B.
+    ///
+    /// ```
+    /// # use dungeonctl::{Stereo, coyote3::{Amplitude, Frequency, Pulse}};
+    /// let pulses = Stereo {
+    ///     a: Pulse::new(Frequency::hz(100)?, Amplitude::from(50)),
+    ///     b: Pulse::silent(),
+    /// };
+    ///
+    /// assert_eq!(pulses.to_wire(), [10, 50, 0, 0]);
+    /// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+    /// ```
+    pub fn to_wire(&self) -> [u8; 4] {
+        [
+            self.a.compressed_frequency(),
+            self.a.intensity.value(),
+            self.b.compressed_frequency(),
+            self.b.intensity.value(),
+        ]
+    }
+}
+
+/// The amplitude of a [`Pulse`] as an abstract value in the range of 0 to 100.
+///
+/// Converting from a `u8` saturates at 100, use [`Amplitude::new()`] to reject values above
+/// that instead.
+///
+/// ```
+/// # use dungeonctl::coyote3::Amplitude;
+/// assert_eq!(Amplitude::from(150), Amplitude::MAX);
+/// assert!(Amplitude::new(150).is_err());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "u8", into = "u8")
+)]
+pub struct Amplitude(u8);
+
+impl Amplitude {
+    /// No output.
+    pub const ZERO: Amplitude = Amplitude(0);
+    /// The highest amplitude.
+    pub const MAX: Amplitude = Amplitude(100);
+
+    /// Create an amplitude, rejecting values above 100.
+    pub const fn new(value: u8) -> std::result::Result<Self, PulseError> {
+        if value > Self::MAX.0 {
+            return Err(PulseError::IntensityOutOfRange(value));
+        }
+
+        Ok(Self(value))
+    }
+    /// The amplitude in the range of 0 to 100.
+    pub const fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+impl From<u8> for Amplitude {
+    /// Values above 100 saturate at [`Amplitude::MAX`].
+    fn from(value: u8) -> Self {
+        Self(value.min(Self::MAX.0))
+    }
+}
+
+impl From<Amplitude> for u8 {
+    fn from(amplitude: Amplitude) -> Self {
+        amplitude.0
+    }
+}
+
+/// The frequency of a [`Pulse`], limited to what the device supports.
+///
+/// The official documentation states a maximum of 100 Hz, but the device actually accepts up to
+/// 200 Hz. Internally, the device works with the period of the waveform in steps that get coarser
+/// with lower frequencies (see [`compress_frequency()`]), so a `Frequency` stores the compressed
+/// value that is sent to the device.
+///
+/// ```
+/// # use dungeonctl::coyote3::Frequency;
+/// assert_eq!(Frequency::hz(50)?, Frequency::period_ms(20.0)?);
+/// assert_eq!(Frequency::period_ms(250.0)?.to_hz(), 4.0);
+/// assert!(Frequency::hz(201).is_err());
+/// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Frequency(u8);
+
+impl Frequency {
+    /// No output.
+    pub const OFF: Frequency = Frequency(0);
+    /// The highest frequency the device accepts, 200 Hz.
+    pub const MAX: Frequency = Frequency(5);
+    /// The highest frequency the device accepts in Hz.
+    pub const MAX_HZ: u8 = 200;
+
+    /// Create a frequency from a value in Hz in the range of 1 Hz to 200 Hz.
+    ///
+    /// The period is rounded to the nearest step like in [`period_ms()`](Self::period_ms),
+    /// unlike [`compress_frequency()`], which truncates it.
+    pub fn hz(hz: u8) -> std::result::Result<Self, PulseError> {
+        if !(1..=Self::MAX_HZ).contains(&hz) {
+            return Err(PulseError::FrequencyOutOfRange(hz));
+        }
+
+        Self::period_ms(1000.0 / hz as f32)
+    }
+    /// Create a frequency from the period of the waveform in ms in the range of 5 ms to 1000 ms.
+    ///
+    /// The period is rounded to the nearest step the device supports.
+    pub fn period_ms(period: f32) -> std::result::Result<Self, PulseError> {
+        if !(5.0..=1000.0).contains(&period) {
+            return Err(PulseError::PeriodOutOfRange(period));
+        }
+
+        Ok(Self(compress_period_ms(period).round() as u8))
+    }
+    /// The frequency in Hz, or 0 for [`Frequency::OFF`].
+    pub fn to_hz(&self) -> f32 {
+        decompress_frequency_f32(self.0)
+    }
+    /// The compressed value that is sent to the device.
+    pub fn compressed(&self) -> u8 {
+        self.0
+    }
+    /// Create a frequency from the compressed value that is sent to the device, which is either 0
+    /// ([`Frequency::OFF`]) or in the range of 5 to 240.
+    ///
+    /// ```
+    /// # use dungeonctl::coyote3::Frequency;
+    /// assert_eq!(Frequency::from_compressed(10)?, Frequency::hz(100)?);
+    /// assert!(Frequency::from_compressed(241).is_err());
+    /// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+    /// ```
+    pub fn from_compressed(raw: u8) -> std::result::Result<Self, PulseError> {
+        if raw != 0 && !(Self::MAX.0..=240).contains(&raw) {
+            return Err(PulseError::CompressedFrequencyOutOfRange(raw));
+        }
+
+        Ok(Self(raw))
+    }
+}
+
+impl std::fmt::Debug for Frequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Frequency({} Hz)", self.to_hz())
+    }
+}
+
+/// Serialized as the frequency in Hz.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Frequency {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.to_hz())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Frequency {
+    fn deserialize<D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        let hz = f32::deserialize(deserializer)?;
+        if hz == 0.0 {
+            return Ok(Frequency::OFF);
+        }
+
+        Frequency::period_ms(1000.0 / hz).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Convert a frequency in Hz into the compressed value that is sent to the device.
+///
+/// The device expects the period of the waveform in ms, compressed into the range of 5 to 240:
+///
+/// | period        | compressed value          |
+/// |---------------|---------------------------|
+/// | 5 – 100 ms    | period                    |
+/// | 100 – 600 ms  | (period − 100) / 5 + 100  |
+/// | 600 – 1000 ms | (period − 600) / 10 + 200 |
+///
+/// Frequencies above 200 Hz are compressed to 5 (200 Hz), a frequency of 0 is sent as 0,
+/// which results in no output.
+pub fn compress_frequency(hz: u8) -> u8 {
+    if hz == 0 {
+        return 0;
+    }
+
+    compress_period_ms(1000.0 / (hz as f32)) as u8
+}
+
+/// Compress a period in ms as described in [`compress_frequency()`], without rounding.
+fn compress_period_ms(t: f32) -> f32 {
+    #[allow(clippy::match_overlapping_arm)]
+    match t {
+        ..5.0 => 5.0,
+        ..100.0 => t,
+        ..600.0 => (t - 100.0) / 5.0 + 100.0,
+        ..1000.0 => (t - 600.0) / 10.0 + 200.0,
+        _ => 240.0,
+    }
+}
+
+/// Convert a compressed frequency value as sent to the device back into a frequency in Hz.
+///
+/// This is the inverse of [`compress_frequency()`], as far as the compression allows: it returns
+/// the highest frequency that is compressed to `raw`, so
+/// `compress_frequency(decompress_frequency(compress_frequency(hz)))` always equals
+/// `compress_frequency(hz)`. Values outside of 5 to 240 are clamped, except for 0 (no output).
+///
+/// Since the whole range of 200 to 240 maps to frequencies between 1 Hz and 1.67 Hz, all of these
+/// values result in 1 Hz. Use [`decompress_frequency_f32()`] if that precision is needed.
+pub fn decompress_frequency(raw: u8) -> u8 {
+    decompress_frequency_f32(raw) as u8
+}
+
+/// Convert a compressed frequency value as sent to the device back into a fractional frequency
+/// in Hz.
+///
+/// This is useful to display the frequencies of captured packets. The device resolution gets
+/// coarser with lower frequencies: steps of 1 ms period in the range of 5 to 100, 5 ms in the
+/// range of 100 to 200 and 10 ms in the range of 200 to 240, where the last range only covers
+/// frequencies between 1 Hz and 1.67 Hz. Values outside of 5 to 240 are clamped, except for 0
+/// (no output).
+pub fn decompress_frequency_f32(raw: u8) -> f32 {
+    if raw == 0 {
+        return 0.0;
+    }
+
+    1000.0 / compressed_period_ms(raw)
+}
+
+fn compressed_period_ms(raw: u8) -> f32 {
+    let raw = raw.clamp(5, 240) as f32;
+
+    match raw {
+        ..=100.0 => raw,
+        ..=200.0 => (raw - 100.0) * 5.0 + 100.0,
+        _ => (raw - 200.0) * 10.0 + 600.0,
+    }
+}
+
+/// The error returned by the [`Frequency`] and [`Amplitude`] constructors for values outside of
+/// the supported range, and by [`IntensityChange::from_wire()`] for invalid modes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum PulseError {
+    /// The frequency is not in the range of 1 Hz to 200 Hz.
+    FrequencyOutOfRange(u8),
+    /// The period is not in the range of 5 ms to 1000 ms.
+    PeriodOutOfRange(f32),
+    /// The amplitude is not in the range of 0 to 100.
+    IntensityOutOfRange(u8),
+    /// The intensity change mode is not in the range of 0 to 3.
+    InvalidIntensityMode(u8),
+    /// The compressed frequency is neither 0 nor in the range of 5 to 240.
+    CompressedFrequencyOutOfRange(u8),
+}
+
+impl std::fmt::Display for PulseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PulseError::FrequencyOutOfRange(frequency) => {
+                write!(f, "frequency {frequency} Hz is outside of 1 Hz to 200 Hz")
+            }
+            PulseError::PeriodOutOfRange(period) => {
+                write!(f, "period {period} ms is outside of 5 ms to 1000 ms")
+            }
+            PulseError::IntensityOutOfRange(intensity) => {
+                write!(f, "amplitude {intensity} is outside of 0 to 100")
+            }
+            PulseError::InvalidIntensityMode(mode) => {
+                write!(f, "intensity change mode {mode} is outside of 0 to 3")
+            }
+            PulseError::CompressedFrequencyOutOfRange(raw) => {
+                write!(
+                    f,
+                    "compressed frequency {raw} is neither 0 nor within 5 to 240"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for PulseError {}
+
+/// Used to describe if and how the stimulation intensity should be changed.
+///
+/// Note that relative changes should be preferred in many cases over absolute changes since
+/// absolute changes will overwrite any intensity changes that were made using the hardware
+/// “shoulder” switches of the coyote, basically rendering them useless.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "mode", content = "value")
+)]
+pub enum IntensityChange {
+    /// Do not change the intensity.
+    DoNotChange,
+    /// Increase the intensity by `x`.
+    RelativeIncrease(u8),
+    /// Decrease the intensity by `x`.
+    RelativeDecrease(u8),
+    /// Set the intensity to `x`.
+    AbsoluteChange(u8),
+}
+
+impl IntensityChange {
+    /// Decode an intensity change from the 2-bit `mode` and the `value` sent to the device for
+    /// a channel, e.g. to inspect captured `SendPulses` packets.
+    ///
+    /// The value is ignored for mode 0, which doesn't change the intensity.
+    ///
+    /// ```
+    /// # use dungeonctl::coyote3::IntensityChange;
+    /// assert_eq!(IntensityChange::from_wire(0b01, 5)?, IntensityChange::RelativeIncrease(5));
+    /// assert!(IntensityChange::from_wire(0b100, 5).is_err());
+    /// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+    /// ```
+    pub fn from_wire(mode: u8, value: u8) -> std::result::Result<Self, PulseError> {
+        match mode {
+            0b00 => Ok(IntensityChange::DoNotChange),
+            0b01 => Ok(IntensityChange::RelativeIncrease(value)),
+            0b10 => Ok(IntensityChange::RelativeDecrease(value)),
+            0b11 => Ok(IntensityChange::AbsoluteChange(value)),
+            mode => Err(PulseError::InvalidIntensityMode(mode)),
+        }
+    }
+    fn mode(&self) -> u8 {
+        match self {
+            IntensityChange::DoNotChange => 0b00,
+            IntensityChange::RelativeIncrease(_) => 0b01,
+            IntensityChange::RelativeDecrease(_) => 0b10,
+            IntensityChange::AbsoluteChange(_) => 0b11,
+        }
+    }
+    fn limited(self, current: u8, limit: u8) -> Self {
+        match self {
+            IntensityChange::DoNotChange => self,
+            IntensityChange::AbsoluteChange(v) => IntensityChange::AbsoluteChange(v.min(limit)),
+            IntensityChange::RelativeIncrease(v) => {
+                IntensityChange::RelativeIncrease(v.min(limit.saturating_sub(current)))
+            }
+            IntensityChange::RelativeDecrease(v) => {
+                IntensityChange::RelativeDecrease(v.min(current))
+            }
+        }
+    }
+    /// The intensity after applying this change to `current` like the device does, keeping the
+    /// intensity within `limit`.
+    fn applied_to(self, current: u8, limit: u8) -> u8 {
+        match self {
+            IntensityChange::DoNotChange => current,
+            IntensityChange::RelativeIncrease(v) => current.saturating_add(v).min(limit),
+            IntensityChange::RelativeDecrease(v) => current.saturating_sub(v),
+            IntensityChange::AbsoluteChange(v) => v.min(limit),
+        }
+    }
+    fn value(&self) -> u8 {
+        match self {
+            IntensityChange::DoNotChange => 0,
+            IntensityChange::RelativeIncrease(v)
+            | IntensityChange::RelativeDecrease(v)
+            | IntensityChange::AbsoluteChange(v) => *v,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, binrw::BinWrite)]
+#[bw(big)]
+enum Command {
+    #[bw(magic = 0xB0u8)]
+    SendPulses(Pulses),
+    /// Like `SendPulses`, asking the device to report the resulting intensity change with the
+    /// serial number `serial` in the range of 1 to 15.
+    #[bw(magic = 0xB0u8)]
+    SendTrackedPulses {
+        #[bw(ignore)]
+        serial: u8,
+        #[bw(args(*serial))]
+        pulses: Pulses,
+    },
+    #[bw(magic = 0xBFu8)]
+    UpdateSettings(DeviceSettings),
+}
+/// A buffer that fits every [`Command`].
+type CommandBuffer = ArrayVec<u8, 20>;
+
+impl Command {
+    fn to_bytes(self) -> Result<impl Deref<Target = [u8]>> {
+        let mut buf = CommandBuffer::new_const();
+        self.write_into(&mut buf)?;
+        Ok(buf)
+    }
+    /// Serialize the command into `buf`, replacing its previous contents.
+    fn write_into(self, buf: &mut CommandBuffer) -> Result<()> {
+        use binrw::BinWrite;
+
+        buf.clear();
+        self.write_be(&mut binrw::io::NoSeek::new(buf))?;
+        Ok(())
+    }
+}
+#[derive(Debug)]
+enum Update {
+    Notification(ValueNotification),
+    Rssi(Option<i16>),
+    Connected(bool),
+    /// The intensity a command would have resulted in, in a dry run.
+    Intensity(Stereo<u8>),
+    /// The settings a command would have applied, in a dry run.
+    Settings(DeviceSettings),
+}
+
+/// A command as written to the Coyote 3, see [`decode_command()`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum DecodedCommand {
+    /// Send the next pulses (magic 0xB0).
+    SendPulses {
+        /// The serial number the device reports the resulting intensity change with, or 0 if it
+        /// isn't tracked.
+        serial: u8,
+        /// The pulses.
+        pulses: Pulses,
+    },
+    /// Update the device settings (magic 0xBF).
+    UpdateSettings(DeviceSettings),
+}
+
+/// The wire format of [`DecodedCommand`].
+#[derive(binrw::BinRead)]
+#[br(big)]
+enum RawCommand {
+    #[br(magic = 0xB0u8)]
+    SendPulses {
+        header: u8,
+        values: [u8; 2],
+        #[br(try_map = Pulses::parse_pulses)]
+        pulses: [Stereo<Pulse>; 4],
+    },
+    #[br(magic = 0xBFu8)]
+    UpdateSettings(DeviceSettings),
+}
+
+/// Decode the `bytes` of a command as written to the write characteristic (0x150A) of the
+/// Coyote 3, e.g. as encoded by [`encode_send_pulses()`] or [`encode_update_settings()`] on the
+/// other end of another transport.
+///
+/// Unknown commands and malformed ones, including pulses with values the device doesn't support,
+/// fail with [`Error::Protocol`]. The settings are not [validated](DeviceSettings::validate).
+///
+/// ```
+/// # use dungeonctl::coyote3::{DecodedCommand, Pulses, decode_command, encode_send_pulses};
+/// let bytes = encode_send_pulses(&Pulses::silent());
+///
+/// assert_eq!(
+///     decode_command(&bytes)?,
+///     DecodedCommand::SendPulses { serial: 0, pulses: Pulses::silent() }
+/// );
+/// # Ok::<(), dungeonctl::Error>(())
+/// ```
+pub fn decode_command(bytes: &[u8]) -> Result<DecodedCommand> {
+    let command = match RawCommand::read_be(&mut binrw::io::Cursor::new(bytes))? {
+        RawCommand::SendPulses {
+            header,
+            values,
+            pulses,
+        } => {
+            let change = |mode: u8, value| {
+                IntensityChange::from_wire(mode & 0b11, value).expect("the mode is masked")
+            };
+            DecodedCommand::SendPulses {
+                serial: header >> 4,
+                pulses: Pulses {
+                    intensity: Stereo {
+                        a: change(header >> 2, values[0]),
+                        b: change(header, values[1]),
+                    },
+                    pulses,
+                },
+            }
+        }
+        RawCommand::UpdateSettings(settings) => DecodedCommand::UpdateSettings(settings),
+    };
+
+    Ok(command)
+}
+
+/// A notification sent by the Coyote 3, see [`parse_notification()`].
+#[derive(Clone, Copy, Debug, PartialEq, binrw::BinRead, binrw::BinWrite)]
+#[brw(big)]
+#[non_exhaustive]
+pub enum ParsedNotification {
+    /// The stimulation intensity has changed (magic 0xB1).
+    #[brw(magic = 0xB1u8)]
+    IntensityChange {
+        /// The serial number of the command that caused the change, or 0 if it was changed on
+        /// the device itself, see [`IntensitySource`].
+        serial: u8,
+        /// The new stimulation intensity.
+        intensity: Stereo<u8>,
+    },
+    /// The device settings have changed (magic 0xBE).
+    #[brw(magic = 0xBEu8)]
+    DeviceSettingsChange(DeviceSettings),
+}
+
+/// Parse the `bytes` of a notification sent by the Coyote 3 on its notify characteristic
+/// (0x150B), e.g. to decode captured packets without a connection.
+///
+/// Notifications of unknown types and malformed ones fail with [`Error::Protocol`]. The
+/// channels are reported as the device sees them, regardless of
+/// [`Coyote3Builder::swap_channels()`].
+///
+/// ```
+/// # use dungeonctl::{Stereo, coyote3::{ParsedNotification, parse_notification}};
+/// assert_eq!(
+///     parse_notification(&[0xB1, 0x03, 20, 5])?,
+///     ParsedNotification::IntensityChange { serial: 3, intensity: Stereo { a: 20, b: 5 } }
+/// );
+/// # Ok::<(), dungeonctl::Error>(())
+/// ```
+pub fn parse_notification(bytes: &[u8]) -> Result<ParsedNotification> {
+    Ok(ParsedNotification::read_be(&mut binrw::io::Cursor::new(
+        bytes,
+    ))?)
+}
+
+/// Encode the command that sends `pulses` to the Coyote 3, as written to its write
+/// characteristic (0x150A), e.g. to send it over another transport.
+///
+/// The command carries no serial number, so the resulting intensity change is reported like one
+/// made on the device itself, as [`IntensitySource::Device`]. The channels are encoded as given, regardless of
+/// [`Coyote3Builder::swap_channels()`].
+///
+/// ```
+/// # use dungeonctl::coyote3::{Pulses, encode_send_pulses};
+/// assert_eq!(encode_send_pulses(&Pulses::silent())[0], 0xB0);
+/// ```
+pub fn encode_send_pulses(pulses: &Pulses) -> [u8; Pulses::ENCODED_LEN] {
+    encode(Command::SendPulses(*pulses))
+}
+
+/// Encode the command that updates the `settings` of the Coyote 3, see
+/// [`encode_send_pulses()`].
+///
+/// The settings are not [validated](DeviceSettings::validate).
+pub fn encode_update_settings(settings: &DeviceSettings) -> [u8; 7] {
+    encode(Command::UpdateSettings(*settings))
+}
+
+/// Encode a `notification` as sent by the Coyote 3 on its notify characteristic (0x150B), e.g.
+/// to report the state of a device over another transport. This is the inverse of
+/// [`parse_notification()`].
+///
+/// ```
+/// # use dungeonctl::{Stereo, coyote3::{ParsedNotification, encode_notification}};
+/// let notification = ParsedNotification::IntensityChange {
+///     serial: 3,
+///     intensity: Stereo { a: 20, b: 5 },
+/// };
+///
+/// assert_eq!(encode_notification(&notification), [0xB1, 0x03, 20, 5]);
+/// ```
+pub fn encode_notification(notification: &ParsedNotification) -> Vec<u8> {
+    use binrw::BinWrite;
+
+    let mut buf = binrw::io::Cursor::new(Vec::new());
+    notification
+        .write_be(&mut buf)
+        .expect("writing to a Vec doesn't fail");
+    buf.into_inner()
+}
+
+fn encode<const N: usize>(command: Command) -> [u8; N] {
+    command
+        .to_bytes()
+        .expect("commands fit into the buffer")
+        .deref()
+        .try_into()
+        .expect("commands have a fixed length")
+}
+
+impl ParsedNotification {
+    /// The magic bytes of all variants.
+    const MAGIC: [u8; 2] = [0xB1, 0xBE];
+
+    /// Whether the notification is of a known type. Empty notifications are malformed rather
+    /// than unknown.
+    fn is_known(value: &[u8]) -> bool {
+        value
+            .first()
+            .is_none_or(|magic| Self::MAGIC.contains(magic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hex_literal::hex;
+
+    #[test]
+    fn test_parse_notification() {
+        assert_eq!(
+            parse_notification(&hex!("b1001405")).unwrap(),
+            ParsedNotification::IntensityChange {
+                serial: 0,
+                intensity: Stereo { a: 20, b: 5 },
+            }
+        );
+        assert!(matches!(
+            parse_notification(&hex!("b10f0000")).unwrap(),
+            ParsedNotification::IntensityChange { serial: 15, .. }
+        ));
+        assert_eq!(
+            parse_notification(&hex!("be1446a0a00000")).unwrap(),
+            ParsedNotification::DeviceSettingsChange(DeviceSettings {
+                limit: Stereo { a: 20, b: 70 },
+                ..Default::default()
+            })
+        );
+
+        for malformed in [&hex!("b3010203")[..], &hex!("b10714"), &[]] {
+            assert!(matches!(
+                parse_notification(malformed),
+                Err(Error::Protocol(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_b0_command() {
+        assert_eq!(
+            &*Command::SendPulses(Pulses {
+                intensity: Stereo {
+                    a: IntensityChange::AbsoluteChange(10),
+                    b: IntensityChange::AbsoluteChange(0)
+                },
+                pulses: [Stereo {
+                    a: Pulse {
+                        frequency: Frequency::hz(100).unwrap(),
+                        intensity: Amplitude::ZERO
+                    },
+                    b: Pulse {
+                        frequency: Frequency::hz(30).unwrap(),
+                        intensity: Amplitude::ZERO
+                    }
+                }; 4]
+            })
+            .to_bytes()
+            .unwrap(),
+            hex!("b00f0a000a0a0a0a000000002121212100000000")
+        );
+        assert_eq!(
+            &*Command::SendPulses(Pulses {
+                intensity: Stereo {
+                    a: IntensityChange::AbsoluteChange(10),
+                    b: IntensityChange::AbsoluteChange(0)
+                },
+                pulses: [Stereo {
+                    a: Pulse {
+                        frequency: Frequency::hz(100).unwrap(),
+                        intensity: Amplitude::MAX
+                    },
+                    b: Pulse {
+                        frequency: Frequency::hz(30).unwrap(),
+                        intensity: Amplitude::MAX
+                    }
+                }; 4]
+            })
+            .to_bytes()
+            .unwrap(),
+            hex!("b00f0a000a0a0a0a646464642121212164646464")
+        );
+    }
+
+    #[test]
+    fn test_disabled_channel() {
+        let pulses = Pulses {
+            intensity: Stereo::symmetric(IntensityChange::RelativeIncrease(5)),
+            pulses: [Stereo::symmetric(Pulse {
+                frequency: Frequency::hz(100).unwrap(),
+                intensity: Amplitude::MAX,
+            }); 4],
+        };
+
+        assert_eq!(
+            &*Command::SendPulses(pulses.muted(Stereo { a: true, b: false }))
+                .to_bytes()
+                .unwrap(),
+            hex!("b0070500 0a0a0a0a 64646464 00000000 00000000")
+        );
+        assert_eq!(
+            &*Command::SendPulses(pulses.muted(Stereo { a: false, b: true }))
+                .to_bytes()
+                .unwrap(),
+            hex!("b00d0005 00000000 00000000 0a0a0a0a 64646464")
+        );
+        assert_eq!(pulses.muted(Stereo::symmetric(true)), pulses);
+    }
+
+    #[test]
+    fn test_soft_limit() {
+        let pulses = |a, b| Pulses {
+            intensity: Stereo { a, b },
+            ..Pulses::silent()
+        };
+        let current = Stereo { a: 20, b: 40 };
+        let limit = Stereo { a: 30, b: 50 };
+
+        assert_eq!(
+            &*Command::SendPulses(
+                pulses(
+                    IntensityChange::AbsoluteChange(80),
+                    IntensityChange::AbsoluteChange(50)
+                )
+                .limited(current, limit)
+            )
+            .to_bytes()
+            .unwrap(),
+            hex!("b00f1e3200000000000000000000000000000000")
+        );
+
+        let relative = pulses(
+            IntensityChange::RelativeIncrease(20),
+            IntensityChange::RelativeDecrease(20),
+        )
+        .limited(current, limit);
+        assert_eq!(
+            relative.intensity,
+            Stereo {
+                a: IntensityChange::RelativeIncrease(10),
+                b: IntensityChange::RelativeDecrease(20),
+            }
+        );
+        assert_eq!(
+            IntensityChange::RelativeIncrease(5).limited(40, 30),
+            IntensityChange::RelativeIncrease(0)
+        );
+        assert_eq!(
+            IntensityChange::RelativeDecrease(50).limited(40, 30),
+            IntensityChange::RelativeDecrease(40)
+        );
+    }
+
+    #[test]
+    fn test_apply_intensity_change() {
+        assert_eq!(IntensityChange::DoNotChange.applied_to(20, 10), 20);
+        assert_eq!(IntensityChange::RelativeIncrease(10).applied_to(5, 10), 10);
+        assert_eq!(IntensityChange::RelativeDecrease(10).applied_to(5, 10), 0);
+        assert_eq!(IntensityChange::AbsoluteChange(200).applied_to(5, 70), 70);
+    }
+
+    #[test]
+    fn test_intensity_change_wire() {
+        for change in [
+            IntensityChange::DoNotChange,
+            IntensityChange::RelativeIncrease(10),
+            IntensityChange::RelativeDecrease(255),
+            IntensityChange::AbsoluteChange(0),
+        ] {
+            assert_eq!(
+                IntensityChange::from_wire(change.mode(), change.value()),
+                Ok(change)
+            );
+        }
+
+        assert_eq!(
+            IntensityChange::from_wire(0b00, 20),
+            Ok(IntensityChange::DoNotChange)
+        );
+        assert_eq!(
+            IntensityChange::from_wire(4, 20),
+            Err(PulseError::InvalidIntensityMode(4))
+        );
+    }
+
+    #[test]
+    fn test_undo_projection() {
+        let increase = Pulses {
+            intensity: Stereo::symmetric(IntensityChange::RelativeIncrease(5)),
+            ..Pulses::silent()
+        };
+        let limit = Stereo::symmetric(50);
+        let reported = Stereo::symmetric(10);
+        let mut projection = Projection::new(reported);
+
+        // pulses that failed to send don't count towards the projection
+        let previous = projection;
+        projection.apply(reported, increase, limit);
+        let applied = projection;
+        projection.undo(applied, previous);
+        assert_eq!(projection.projected, reported);
+
+        // unless other pulses were projected while sending
+        projection.apply(reported, increase, limit);
+        let applied = projection;
+        projection.apply(reported, increase, limit);
+        projection.undo(applied, previous);
+        assert_eq!(projection.projected, Stereo::symmetric(20));
+    }
+
+    #[test]
+    fn test_projected_intensity() {
+        let pulses = |a, b| Pulses {
+            intensity: Stereo { a, b },
+            ..Pulses::silent()
+        };
+        let limit = Stereo { a: 30, b: 50 };
+        let reported = Stereo { a: 20, b: 10 };
+        let mut projection = Projection::new(reported);
+
+        let sent = projection.apply(
+            reported,
+            pulses(
+                IntensityChange::RelativeIncrease(8),
+                IntensityChange::RelativeDecrease(4),
+            ),
+            limit,
+        );
+        assert_eq!(
+            sent,
+            pulses(
+                IntensityChange::RelativeIncrease(8),
+                IntensityChange::RelativeDecrease(4),
+            )
+        );
+        assert_eq!(projection.projected, Stereo { a: 28, b: 6 });
+
+        // the device did not report the changes yet, so they are clamped to the projection
+        let sent = projection.apply(
+            reported,
+            pulses(
+                IntensityChange::RelativeIncrease(8),
+                IntensityChange::RelativeDecrease(10),
+            ),
+            limit,
+        );
+        assert_eq!(
+            sent,
+            pulses(
+                IntensityChange::RelativeIncrease(2),
+                IntensityChange::RelativeDecrease(6),
+            )
+        );
+        assert_eq!(projection.projected, Stereo { a: 30, b: 0 });
+
+        // the device reports the intensity that was projected
+        projection.reconcile(Stereo { a: 30, b: 0 });
+        assert_eq!(projection.projected, Stereo { a: 30, b: 0 });
+
+        // the hardware buttons lowered the intensity
+        let sent = projection.apply(
+            Stereo { a: 25, b: 0 },
+            pulses(
+                IntensityChange::RelativeIncrease(10),
+                IntensityChange::DoNotChange,
+            ),
+            limit,
+        );
+        assert_eq!(sent.intensity.a, IntensityChange::RelativeIncrease(5));
+        assert_eq!(projection.projected, Stereo { a: 30, b: 0 });
+    }
+
+    #[test]
+    fn test_limit_check() {
+        let pulses = |amplitude: u8| Pulses {
+            pulses: [Stereo::symmetric(Pulse::new(
+                Frequency::hz(100).unwrap(),
+                Amplitude::from(amplitude),
+            )); 4],
+            ..Pulses::silent()
+        };
+        let intensity = Stereo { a: 100, b: 50 };
+        let limit = Stereo { a: 70, b: 70 };
+
+        // at the limit
+        for check in [LimitCheck::Clamp, LimitCheck::Reject] {
+            assert_eq!(
+                pulses(70).checked_output(intensity, limit, check).unwrap(),
+                pulses(70)
+            );
+        }
+
+        // over the limit on channel A only
+        let clamped = pulses(90)
+            .checked_output(intensity, limit, LimitCheck::Clamp)
+            .unwrap();
+        assert_eq!(clamped.pulses.map(|p| p.a.intensity.value()), [70; 4]);
+        assert_eq!(clamped.pulses.map(|p| p.b.intensity.value()), [90; 4]);
+        assert!(matches!(
+            pulses(90).checked_output(intensity, limit, LimitCheck::Reject),
+            Err(Error::LimitExceeded {
+                channel: Channel::A,
+                output: 90,
+                limit: 70
+            })
+        ));
+
+        // a lower amplitude within the frame is kept
+        let mut mixed = pulses(90);
+        mixed.pulses[0].a.intensity = Amplitude::from(20);
+        let clamped = mixed
+            .checked_output(intensity, limit, LimitCheck::Clamp)
+            .unwrap();
+        assert_eq!(
+            clamped.pulses.map(|p| p.a.intensity.value()),
+            [20, 70, 70, 70]
+        );
+    }
+
+    #[test]
+    fn test_silent_command() {
+        assert_eq!(
+            &*Command::SendPulses(Pulses::silent()).to_bytes().unwrap(),
+            hex!("b000000000000000000000000000000000000000")
+        );
+    }
+
+    #[test]
+    fn test_settings_confirmation() {
+        let notification = |value: &[u8]| ValueNotification {
+            uuid: NOTIFY_CHARACTERISTIC_UUID,
+            value: value.to_vec(),
+        };
+        let settings = DeviceSettings::default();
+
+        assert!(settings.confirmed_by(&notification(&hex!("be4646a0a00000"))));
+        assert!(!settings.confirmed_by(&notification(&hex!("be4746a0a00000"))));
+        assert!(!settings.confirmed_by(&notification(&hex!("b1004646"))));
+        assert!(!settings.confirmed_by(&ValueNotification {
+            uuid: BATTERY_CHARACTERISTIC_UUID,
+            value: hex!("be4646a0a00000").to_vec(),
+        }));
+    }
+
+    #[test]
+    fn test_settings_field() {
+        let settings = DeviceSettings {
+            limit: Stereo { a: 50, b: 30 },
+            frequency_balance: Stereo { a: 100, b: 120 },
+            intensity_balance: Stereo { a: 10, b: 20 },
+        };
+        let patched = |field| {
+            let mut settings = settings;
+            *settings.field_mut(field) = Stereo::symmetric(1);
+            settings
+        };
+
+        assert_eq!(
+            patched(SettingsField::Limit),
+            DeviceSettings {
+                limit: Stereo::symmetric(1),
+                ..settings
+            }
+        );
+        assert_eq!(
+            patched(SettingsField::FrequencyBalance),
+            DeviceSettings {
+                frequency_balance: Stereo::symmetric(1),
+                ..settings
+            }
+        );
+        assert_eq!(
+            patched(SettingsField::IntensityBalance),
+            DeviceSettings {
+                intensity_balance: Stereo::symmetric(1),
+                ..settings
+            }
+        );
+    }
+
+    #[test]
+    fn test_validate_settings() {
+        assert_eq!(DeviceSettings::default().validate(), Ok(()));
+        assert_eq!(
+            DeviceSettings {
+                limit: Stereo { a: 200, b: 0 },
+                ..Default::default()
+            }
+            .validate(),
+            Ok(())
+        );
+        assert_eq!(
+            DeviceSettings {
+                limit: Stereo { a: 70, b: 201 },
+                ..Default::default()
+            }
+            .validate(),
+            Err(SettingsError::LimitOutOfRange(201))
+        );
+        assert!(matches!(
+            check_settings(
+                &DeviceSettings {
+                    limit: Stereo::symmetric(255),
+                    ..Default::default()
+                },
+                None
+            ),
+            Err(Error::InvalidSettings(SettingsError::LimitOutOfRange(255)))
+        ));
+    }
+
+    #[test]
+    fn test_new_settings() {
+        let balance = Stereo { a: 160, b: 255 };
+
+        assert_eq!(
+            DeviceSettings::new(Stereo { a: 200, b: 0 }, balance, Stereo::symmetric(0)),
+            Ok(DeviceSettings {
+                limit: Stereo { a: 200, b: 0 },
+                frequency_balance: balance,
+                intensity_balance: Stereo::symmetric(0),
+            })
+        );
+        assert_eq!(
+            DeviceSettings::new(Stereo { a: 201, b: 0 }, balance, balance),
+            Err(SettingsError::LimitOutOfRange(201))
+        );
+        assert_eq!(
+            DeviceSettings::new(Stereo { a: 0, b: 255 }, balance, balance),
+            Err(SettingsError::LimitOutOfRange(255))
+        );
+        // the balance parameters take the whole range
+        assert!(
+            DeviceSettings::new(
+                Stereo::symmetric(70),
+                Stereo::symmetric(255),
+                Stereo::symmetric(255)
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_stop_command() {
+        assert_eq!(
+            &*Command::SendPulses(Pulses::STOP).to_bytes().unwrap(),
+            hex!("b00f000000000000000000000000000000000000")
+        );
+    }
+
+    #[test]
+    fn test_amplitude() {
+        assert_eq!(Amplitude::new(0), Ok(Amplitude::ZERO));
+        assert_eq!(Amplitude::new(100), Ok(Amplitude::MAX));
+        assert_eq!(
+            Amplitude::new(101),
+            Err(PulseError::IntensityOutOfRange(101))
+        );
+        assert_eq!(Amplitude::from(0), Amplitude::ZERO);
+        assert_eq!(Amplitude::from(100), Amplitude::MAX);
+        assert_eq!(Amplitude::from(101), Amplitude::MAX);
+        assert_eq!(Amplitude::from(255), Amplitude::MAX);
+        assert_eq!(Amplitude::from(42).value(), 42);
+    }
+
+    #[test]
+    fn test_pulse_lerp() {
+        let from = Pulse::new(Frequency::hz(100).unwrap(), Amplitude::ZERO);
+        let to = Pulse::new(Frequency::period_ms(200.0).unwrap(), Amplitude::from(80));
+
+        assert_eq!(from.lerp(&to, 0.0), from);
+        assert_eq!(
+            from.lerp(&to, 0.5),
+            Pulse::new(Frequency::period_ms(105.0).unwrap(), Amplitude::from(40))
+        );
+        assert_eq!(from.lerp(&to, 1.0), to);
+        assert_eq!(from.lerp(&to, -1.0), from);
+        assert_eq!(from.lerp(&to, 2.0), to);
+
+        let silent = Pulse::silent();
+        assert_eq!(
+            silent.lerp(&to, 0.5),
+            Pulse::new(to.frequency, Amplitude::from(40))
+        );
+        assert_eq!(
+            silent.lerp(&to, 0.0),
+            Pulse::new(to.frequency, Amplitude::ZERO)
+        );
+        assert_eq!(silent.lerp(&silent, 0.5), silent);
+    }
+
+    #[test]
+    fn test_frequency_hz() {
+        assert_eq!(Frequency::hz(0), Err(PulseError::FrequencyOutOfRange(0)));
+        assert_eq!(Frequency::hz(1).map(|f| f.compressed()), Ok(240));
+        assert_eq!(Frequency::hz(100).map(|f| f.compressed()), Ok(10));
+        assert_eq!(Frequency::hz(200).map(|f| f.compressed()), Ok(5));
+        assert_eq!(
+            Frequency::hz(201),
+            Err(PulseError::FrequencyOutOfRange(201))
+        );
+        assert_eq!(Frequency::hz(4).unwrap().to_hz(), 4.0);
+        assert_eq!(Frequency::OFF.compressed(), 0);
+        assert_eq!(Frequency::OFF.to_hz(), 0.0);
+    }
+
+    #[test]
+    fn test_max_frequency() {
+        assert_eq!(Frequency::hz(Frequency::MAX_HZ), Ok(Frequency::MAX));
+        assert_eq!(Frequency::MAX.to_hz(), 200.0);
+        assert!(Frequency::hz(Frequency::MAX_HZ + 1).is_err());
+    }
+
+    #[test]
+    fn test_frequency_period() {
+        assert_eq!(Frequency::period_ms(5.0).map(|f| f.compressed()), Ok(5));
+        assert_eq!(Frequency::period_ms(7.6).map(|f| f.compressed()), Ok(8));
+        assert_eq!(Frequency::period_ms(150.0).map(|f| f.compressed()), Ok(110));
+        assert_eq!(
+            Frequency::period_ms(1000.0).map(|f| f.compressed()),
+            Ok(240)
+        );
+        assert_eq!(Frequency::period_ms(150.0).unwrap().to_hz(), 1000.0 / 150.0);
+        assert_eq!(Frequency::period_ms(10.0), Frequency::hz(100));
+
+        for period in [4.9, 1000.5, -10.0, f32::INFINITY] {
+            assert_eq!(
+                Frequency::period_ms(period),
+                Err(PulseError::PeriodOutOfRange(period))
+            );
+        }
+        assert!(Frequency::period_ms(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn test_frequency_rounding() {
+        // 333.33 ms are compressed to 146.67
+        assert_eq!(Frequency::hz(3).map(|f| f.compressed()), Ok(147));
+        assert_eq!(compress_frequency(3), 146);
+        // 142.86 ms are compressed to 108.57
+        assert_eq!(Frequency::hz(7).map(|f| f.compressed()), Ok(109));
+
+        for hz in 1..=Frequency::MAX_HZ {
+            assert_eq!(
+                Frequency::hz(hz),
+                Frequency::period_ms(1000.0 / f32::from(hz))
+            );
+        }
+    }
+
+    #[test]
+    fn test_frequency_compression() {
+        assert_eq!(compress_frequency(0), 0);
+        assert_eq!(decompress_frequency(0), 0);
+
+        for (hz, raw) in [(1, 240), (2, 180), (10, 100), (30, 33), (100, 10), (200, 5)] {
+            assert_eq!(compress_frequency(hz), raw);
+            assert_eq!(decompress_frequency(raw), hz);
+        }
+
+        for hz in 1..=200 {
+            let raw = compress_frequency(hz);
+            assert!((5..=240).contains(&raw));
+            assert!(decompress_frequency(raw) >= hz);
+            assert_eq!(compress_frequency(decompress_frequency(raw)), raw);
+        }
+    }
+
+    #[test]
+    fn test_fractional_frequency_decompression() {
+        assert_eq!(decompress_frequency_f32(0), 0.0);
+        assert_eq!(decompress_frequency_f32(5), 200.0);
+        assert_eq!(decompress_frequency_f32(100), 10.0);
+        assert_eq!(decompress_frequency_f32(180), 2.0);
+        assert_eq!(decompress_frequency_f32(220), 1.25);
+        assert_eq!(decompress_frequency_f32(240), 1.0);
+        assert_eq!(decompress_frequency_f32(255), 1.0);
+        assert_eq!(decompress_frequency(220), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let pulses = Pulses {
+            intensity: Stereo {
+                a: IntensityChange::RelativeIncrease(5),
+                b: IntensityChange::DoNotChange,
+            },
+            pulses: [Stereo::symmetric(Pulse {
+                frequency: Frequency::hz(100).unwrap(),
+                intensity: Amplitude::from(50),
+            }); 4],
+        };
+        let json = serde_json::to_value(pulses).unwrap();
+
+        assert_eq!(
+            json["intensity"],
+            serde_json::json!({
+                "a": { "mode": "RelativeIncrease", "value": 5 },
+                "b": { "mode": "DoNotChange" },
+            })
+        );
+        assert_eq!(
+            json["pulses"][0],
+            serde_json::json!({
+                "a": { "frequency": 100.0, "intensity": 50 },
+                "b": { "frequency": 100.0, "intensity": 50 },
+            })
+        );
+
+        let deserialized: Pulses = serde_json::from_value(json).unwrap();
+        assert_eq!(deserialized.intensity, pulses.intensity);
+        assert_eq!(deserialized.pulses, pulses.pulses);
+
+        let settings = DeviceSettings::default();
+        let json = serde_json::to_string(&settings).unwrap();
+        assert_eq!(
+            serde_json::from_str::<DeviceSettings>(&json).unwrap(),
+            settings
+        );
+    }
+
+    /// Set `states` one after the other, collecting the changes `signal` reports in between.
+    fn followed_changes<S: futures_signals::signal::Signal + Unpin>(
+        source: &crate::core::StateSource<State>,
+        signal: &mut S,
+        states: impl IntoIterator<Item = State>,
+    ) -> Vec<S::Item> {
+        let mut cx = std::task::Context::from_waker(futures::task::noop_waker_ref());
+
+        states
+            .into_iter()
+            .filter_map(|state| {
+                source.set(state);
+                match std::pin::Pin::new(&mut *signal).poll_change(&mut cx) {
+                    std::task::Poll::Ready(change) => change,
+                    std::task::Poll::Pending => None,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_intensity_signal() {
+        let initial = State {
+            battery: 100,
+            intensity: Stereo { a: 10, b: 0 },
+            ..Default::default()
+        };
+        let updates = [
+            State {
+                battery: 99,
+                ..initial
+            },
+            State {
+                intensity: Stereo { a: 10, b: 5 },
+                ..initial
+            },
+            State {
+                battery: 98,
+                intensity: Stereo { a: 10, b: 5 },
+                ..initial
+            },
+            State {
+                intensity: Stereo { a: 20, b: 5 },
+                ..initial
+            },
+        ];
+        let (source, state) = DeviceState::new(initial);
+        let mut intensity = state.derive(|state: &State| state.intensity);
+
+        assert_eq!(
+            followed_changes(&source, &mut intensity, updates),
+            [Stereo { a: 10, b: 5 }, Stereo { a: 20, b: 5 }]
+        );
+        assert_eq!(intensity.get(), Stereo { a: 20, b: 5 });
+    }
+
+    #[test]
+    fn test_display() {
+        let state = State {
+            battery: 85,
+            intensity: Stereo { a: 20, b: 0 },
+            settings: DeviceSettings::default(),
+            rssi: Some(-60),
+            connected: true,
+        };
+
+        assert_eq!(state.to_string(), "A:20 B:0 batt:85% limit A70/B70");
+        assert_eq!(
+            State {
+                connected: false,
+                ..state
+            }
+            .to_string(),
+            "A:20 B:0 batt:85% limit A70/B70 disconnected"
+        );
+        assert_eq!(
+            DeviceSettings::default().to_string(),
+            "limit A70/B70 freq-balance A160/B160 int-balance A0/B0"
+        );
+    }
+
+    #[test]
+    fn test_connected_signal() {
+        let initial = State {
+            connected: true,
+            ..Default::default()
+        };
+        let updates = [
+            Update::Rssi(Some(-60)),
+            Update::Connected(false),
+            Update::Connected(false),
+            Update::Connected(true),
+        ]
+        .map({
+            let mut state = initial;
+
+            move |update| {
+                state.apply(update);
+                state
+            }
+        });
+        let (source, state) = DeviceState::new(initial);
+        let mut connected = state.derive(|state| state.connected);
+
+        assert_eq!(
+            followed_changes(&source, &mut connected, updates),
+            [false, true]
+        );
+        assert!(connected.get());
+    }
+
+    #[test]
+    fn test_settings_signal() {
+        let limited = DeviceSettings {
+            limit: Stereo { a: 50, b: 70 },
+            ..Default::default()
+        };
+        let updates = [
+            Update::Notification(ValueNotification {
+                uuid: NOTIFY_CHARACTERISTIC_UUID,
+                value: hex!("b1001405").to_vec(),
+            }),
+            Update::Notification(ValueNotification {
+                uuid: BATTERY_CHARACTERISTIC_UUID,
+                value: vec![90],
+            }),
+            Update::Settings(limited),
+            Update::Rssi(Some(-60)),
+            Update::Settings(limited),
+            Update::Settings(DeviceSettings::default()),
+        ]
+        .map({
+            let mut state = State::default();
+
+            move |update| {
+                state.apply(update);
+                state
+            }
+        });
+        let (source, state) = DeviceState::new(State::default());
+        let mut settings = state.derive(|state| state.settings);
+
+        assert_eq!(
+            followed_changes(&source, &mut settings, updates),
+            [limited, DeviceSettings::default()]
+        );
+        assert_eq!(settings.get(), DeviceSettings::default());
+    }
+
+    #[test]
+    fn test_intensity_events() {
+        let notification = |value: &[u8]| ValueNotification {
+            uuid: NOTIFY_CHARACTERISTIC_UUID,
+            value: value.to_vec(),
+        };
+        assert_eq!(
+            Event::from_notification(
+                &notification(&hex!("b1001405")),
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            )
+            .map(Result::unwrap),
+            Some(Event::IntensityChanged {
+                source: IntensitySource::Device,
+                origin: IntensityOrigin::Remote,
+                intensity: Stereo { a: 20, b: 5 },
+            })
+        );
+        assert_eq!(
+            Event::from_notification(
+                &notification(&hex!("b1071400")),
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            )
+            .map(Result::unwrap),
+            Some(Event::IntensityChanged {
+                source: IntensitySource::Command { serial: 7 },
+                origin: IntensityOrigin::Remote,
+                intensity: Stereo { a: 20, b: 0 },
+            })
+        );
+        assert_eq!(
+            Event::from_notification(
+                &notification(&hex!("be4646a0a00000")),
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            )
+            .map(Result::unwrap),
+            None
+        );
+        assert_eq!(
+            Event::from_notification(
+                &ValueNotification {
+                    uuid: BATTERY_CHARACTERISTIC_UUID,
+                    value: vec![0xb1, 0, 0, 0],
+                },
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            )
+            .map(Result::unwrap),
+            None
+        );
+    }
+
+    #[test]
+    fn test_intensity_origin() {
+        let mut serials = Serials::default();
+        serials.issue();
+        // serial numbers handed out before the stream was created are not outstanding
+        let mut outstanding = Outstanding::new(&serials);
+
+        let mut origin = |serials: &Serials, serial: u8| {
+            let notification = ValueNotification {
+                uuid: NOTIFY_CHARACTERISTIC_UUID,
+                value: vec![0xb1, serial, 20, 0],
+            };
+            let origin = |serial| outstanding.report(serials, serial);
+            match Event::from_notification(&notification, origin, DeviceSettings::default()) {
+                Some(Ok(Event::IntensityChanged { origin, .. })) => origin,
+                event => panic!("unexpected event {event:?}"),
+            }
+        };
+        assert_eq!(origin(&serials, 1), IntensityOrigin::Remote);
+
+        assert_eq!(
+            (0..3).map(|_| serials.issue()).collect::<Vec<_>>(),
+            [2, 3, 4]
+        );
+        assert_eq!(origin(&serials, 0), IntensityOrigin::Remote);
+        assert_eq!(origin(&serials, 3), IntensityOrigin::Local);
+        // each command is only counted once
+        assert_eq!(origin(&serials, 3), IntensityOrigin::Remote);
+        assert_eq!(origin(&serials, 5), IntensityOrigin::Remote);
+
+        // the serial numbers wrap around without ever handing out 0
+        assert_eq!((0..14).map(|_| serials.issue()).last(), Some(3));
+        for serial in 1..=15 {
+            assert_eq!(origin(&serials, serial), IntensityOrigin::Local);
+        }
+        assert_eq!(origin(&serials, 16), IntensityOrigin::Remote);
+
+        // once reported, old serial numbers are free for other controllers again
+        assert_eq!(origin(&serials, 7), IntensityOrigin::Remote);
+        serials.issue();
+        assert_eq!(origin(&serials, 4), IntensityOrigin::Local);
+        assert_eq!(origin(&serials, 7), IntensityOrigin::Remote);
+
+        assert_eq!(
+            &*Command::SendTrackedPulses {
+                serial: 3,
+                pulses: Pulses::STOP
+            }
+            .to_bytes()
+            .unwrap(),
+            hex!("b03f0000" "00000000000000000000000000000000")
+        );
+    }
+
+    #[test]
+    fn test_battery_events() {
+        let mut monitor = BatteryMonitor::new(20, 10);
+        let mut events = |levels: &[u8]| {
+            levels
+                .iter()
+                .filter_map(|&battery| monitor.update(battery))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(events(&[80, 21]), []);
+        assert_eq!(events(&[20, 19]), [BatteryEvent::Low]);
+        // hysteresis around the low threshold
+        assert_eq!(events(&[21, 25, 20]), []);
+        assert_eq!(events(&[26]), [BatteryEvent::Recovered]);
+        assert_eq!(
+            events(&[20, 10]),
+            [BatteryEvent::Low, BatteryEvent::Critical]
+        );
+        // hysteresis around the critical threshold
+        assert_eq!(events(&[15, 10]), []);
+        // leaving critical quietly
+        assert_eq!(events(&[16]), []);
+        assert_eq!(events(&[10]), [BatteryEvent::Critical]);
+        assert_eq!(events(&[50]), [BatteryEvent::Recovered]);
+
+        // starting below a threshold reports it right away
+        let mut monitor = BatteryMonitor::new(20, 10);
+        assert_eq!(monitor.update(5), Some(BatteryEvent::Critical));
+    }
+
+    #[test]
+    fn test_unknown_notification() {
+        let notification = ValueNotification {
+            uuid: NOTIFY_CHARACTERISTIC_UUID,
+            value: hex!("b3010203").to_vec(),
+        };
+
+        assert_eq!(
+            Event::from_notification(
+                &notification,
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            )
+            .map(Result::unwrap),
+            Some(Event::Unknown {
+                magic: 0xb3,
+                payload: vec![1, 2, 3],
+            })
+        );
+
+        let mut state = State::default();
+        assert!(!state.apply(Update::Notification(notification)));
+        assert!(matches!(
+            Event::from_notification(
+                &ValueNotification {
+                    uuid: NOTIFY_CHARACTERISTIC_UUID,
+                    value: Vec::new(),
+                },
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            ),
+            Some(Err(Error::Protocol(_)))
+        ));
+    }
+
+    #[test]
+    fn test_settings_drift() {
+        let notification = ValueNotification {
+            uuid: NOTIFY_CHARACTERISTIC_UUID,
+            value: hex!("be1446a0a00000").to_vec(),
+        };
+        let intended = DeviceSettings {
+            limit: Stereo { a: 20, b: 70 },
+            ..Default::default()
+        };
+
+        // the device confirmed the settings
+        assert!(
+            Event::from_notification(&notification, |_| IntensityOrigin::Remote, intended)
+                .is_none()
+        );
+        // another app raised the limit
+        assert_eq!(
+            Event::from_notification(
+                &ValueNotification {
+                    uuid: NOTIFY_CHARACTERISTIC_UUID,
+                    value: hex!("be6446a0a00000").to_vec(),
+                },
+                |_| IntensityOrigin::Remote,
+                intended
+            )
+            .map(Result::unwrap),
+            Some(Event::SettingsDrift {
+                intended,
+                reported: DeviceSettings {
+                    limit: Stereo { a: 100, b: 70 },
+                    ..Default::default()
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_notification() {
+        let notification = ValueNotification {
+            uuid: NOTIFY_CHARACTERISTIC_UUID,
+            value: hex!("b10714").to_vec(),
+        };
+
+        assert!(matches!(
+            Event::from_notification(
+                &notification,
+                |_| IntensityOrigin::Remote,
+                DeviceSettings::default()
+            ),
+            Some(Err(Error::Protocol(_)))
+        ));
+
+        let mut state = State::default();
+        assert!(!state.apply(Update::Notification(notification)));
+        assert_eq!(state, State::default());
+    }
+
+    #[test]
+    fn test_connect_safe() {
+        let builder = Coyote3::connect_safe();
+
+        assert_eq!(builder.settings.limit, Stereo { a: 20, b: 20 });
+        assert_eq!(
+            builder.settings.frequency_balance,
+            DeviceSettings::default().frequency_balance
+        );
+        assert_eq!(builder.safety_threshold, Some(20));
+        assert_eq!(Coyote3::connect().settings, DeviceSettings::default());
+    }
+
+    #[test]
+    fn test_device_name() {
+        let properties = |local_name: &str| btleplug::api::PeripheralProperties {
+            local_name: Some(local_name.to_owned()),
+            ..Default::default()
+        };
+
+        let builder = Coyote3::connect();
+        assert!(builder.filter().matches(&properties("47L121000")));
+        assert!(!builder.filter().matches(&properties("47L121001")));
+
+        let builder = Coyote3::connect().device_name("47L121001");
+        assert!(!builder.filter().matches(&properties("47L121000")));
+        assert!(builder.filter().matches(&properties("47L121001")));
+    }
+
+    #[test]
+    fn test_advertisement() {
+        // a service data advertisement for 0x180A and manufacturer data of company 0x0059
+        let service = btleplug::api::bleuuid::uuid_from_u16(0x180A);
+        let properties = PeripheralProperties {
+            local_name: Some(DEVICE_NAME.to_owned()),
+            services: vec![MAIN_SERVICE_UUID],
+            service_data: HashMap::from([(service, hex!("0164").to_vec())]),
+            manufacturer_data: HashMap::from([(0x0059, hex!("47 4c 01").to_vec())]),
+            tx_power_level: Some(-4),
+            ..Default::default()
+        };
+
+        let advertisement = Advertisement::from(&properties);
+        assert_eq!(advertisement.services, [MAIN_SERVICE_UUID]);
+        assert_eq!(advertisement.service_data[&service], [0x01, 0x64]);
+        assert_eq!(advertisement.manufacturer_data[&0x0059], b"GL\x01");
+        assert_eq!(advertisement.tx_power_level, Some(-4));
+
+        assert_eq!(
+            Advertisement::from(&PeripheralProperties::default()),
+            Advertisement::default()
+        );
+    }
+
+    #[test]
+    fn test_rssi_hysteresis() {
+        assert!(!rssi_changed(None, None));
+        assert!(rssi_changed(None, Some(-60)));
+        assert!(rssi_changed(Some(-60), None));
+        assert!(!rssi_changed(Some(-60), Some(-64)));
+        assert!(!rssi_changed(Some(-60), Some(-56)));
+        assert!(rssi_changed(Some(-60), Some(-65)));
+        assert!(rssi_changed(Some(-60), Some(-55)));
+    }
+
+    #[test]
+    fn test_adapter_selection() {
+        let infos = [
+            "hci0 (usb:v1D6Bp0246d0552)",
+            "hci1 (usb:v0A12p0001d8891)",
+            "hci10",
+        ]
+        .map(String::from);
+
+        assert_eq!(AdapterChoice::First.select(&infos), Some(0));
+        assert_eq!(AdapterChoice::First.select(&[]), None);
+        assert_eq!(AdapterChoice::Index(2).select(&infos), Some(2));
+        assert_eq!(AdapterChoice::Index(3).select(&infos), None);
+        assert_eq!(AdapterChoice::Name("hci1".into()).select(&infos), Some(1));
+        assert_eq!(AdapterChoice::Name("hci10".into()).select(&infos), Some(2));
+        assert_eq!(AdapterChoice::Name("hci2".into()).select(&infos), None);
+    }
+
+    #[test]
+    fn test_adapter_enumeration() {
+        let infos = ["hci0", "hci1", "hci2"].map(String::from);
+        let adapters = || vec!["first", "second", "third"];
+
+        assert_eq!(
+            AdapterChoice::First.pick(adapters(), &infos).unwrap(),
+            "first"
+        );
+        assert_eq!(
+            AdapterChoice::Name("hci2".into())
+                .pick(adapters(), &infos)
+                .unwrap(),
+            "third"
+        );
+        assert!(matches!(
+            AdapterChoice::Index(3).pick(adapters(), &infos),
+            Err(Error::AdapterUnavailable)
+        ));
+        assert!(matches!(
+            AdapterChoice::First.pick(Vec::<&str>::new(), &[]),
+            Err(Error::AdapterUnavailable)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reliable_settings() {
+        assert_eq!(
+            Coyote3::connect().settings_write_type(),
+            WriteType::WithoutResponse
+        );
+        assert_eq!(
+            Coyote3::connect()
+                .reliable_settings(true)
+                .settings_write_type(),
+            WriteType::WithResponse
+        );
+
+        let transport = writer::RecordingTransport::default();
+        let writer = Writer::spawn(transport.clone(), WRITE_QUEUE_DEPTH, Span::none());
+        let settings = Command::UpdateSettings(DeviceSettings::default());
+        writer
+            .send(settings, WriteType::WithResponse)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            transport.written(),
+            [(
+                settings.to_bytes().unwrap().to_vec(),
+                WriteType::WithResponse
+            )]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_play() {
+        let frames = [Pattern::Pulse, Pattern::Wave, Pattern::Ramp].map(|p| p.pulses_at(0.5, 50));
+
+        for period in [PULSE_INTERVAL, Duration::from_millis(200)] {
+            let transport = writer::RecordingTransport::default();
+            let writer = Writer::spawn(transport.clone(), WRITE_QUEUE_DEPTH, Span::none());
+
+            let start = tokio::time::Instant::now();
+            let interval = Interval::new(Arc::new(TokioTimer), period);
+            play(futures::stream::iter(frames), interval, |pulses| {
+                writer.send(Command::SendPulses(pulses), WriteType::WithoutResponse)
+            })
+            .await
+            .unwrap();
+
+            assert_eq!(start.elapsed(), 2 * period);
+            let written = transport.written();
+            assert_eq!(written.len(), 3);
+            for ((data, _), pulses) in written.iter().zip(frames) {
+                assert_eq!(**data, *Command::SendPulses(pulses).to_bytes().unwrap());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_raw_pulses() {
+        let pulses = WaveformBuilder::new()
+            .intensity(Stereo {
+                a: IntensityChange::AbsoluteChange(30),
+                b: IntensityChange::DoNotChange,
+            })
+            .a(Frequency::hz(100).unwrap(), Envelope::Constant(50))
+            .build();
+
+        for (swap_channels, expected) in [(false, pulses), (true, pulses.swapped())] {
+            let transport = writer::RecordingTransport::default();
+            let writer = Writer::spawn(transport.clone(), WRITE_QUEUE_DEPTH, Span::none());
+            let emergency = Mutex::new(EmergencyStop::default());
+
+            send_raw(&writer, &emergency, &pulses, swap_channels)
+                .await
+                .unwrap();
+
+            // written once as they are, without a serial number
+            assert_eq!(
+                transport.written(),
+                [(
+                    encode_send_pulses(&expected).to_vec(),
+                    WriteType::WithoutResponse
+                )]
+            );
+
+            emergency.lock().unwrap().engaged = true;
+            assert!(matches!(
+                send_raw(&writer, &emergency, &pulses, swap_channels).await,
+                Err(Error::EmergencyStopped)
+            ));
+            assert_eq!(transport.written().len(), 1);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_resumes_after_reconnect() {
+        let waveform = Pattern::Wave.pulses_at(0.5, 50);
+        let state = Mutex::new(StreamState {
+            pulses: Pulses {
+                intensity: Stereo::symmetric(IntensityChange::RelativeIncrease(5)),
+                ..waveform
+            },
+            ramp: None,
+        });
+        let connected = Mutex::new(false);
+        let sent = Mutex::new(Vec::new());
+
+        let interval = Interval::new(Arc::new(TokioTimer), PULSE_INTERVAL);
+        let stream = stream_pulses(&state, interval, |next| {
+            let (connected, sent) = (&connected, &sent);
+            async move {
+                if !*connected.lock().unwrap() {
+                    return Err(btleplug::Error::NotConnected.into());
+                }
+                sent.lock().unwrap().push(next);
+                Ok(())
+            }
+        });
+        let reconnect = async {
+            // lose the connection for three ticks
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            *connected.lock().unwrap() = true;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        };
+        futures::pin_mut!(stream);
+        futures::future::select(stream, std::pin::pin!(reconnect)).await;
+
+        // the intensity change is only sent once the connection is back, then the waveform repeats
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [
+                Pulses {
+                    intensity: Stereo::symmetric(IntensityChange::RelativeIncrease(5)),
+                    ..waveform
+                },
+                waveform,
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_during_emergency_stop() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tracing_subscriber::{Layer, layer::SubscriberExt};
+
+        /// Counts the errors that are logged.
+        struct CountErrors(Arc<AtomicUsize>);
+
+        impl<S: tracing::Subscriber> Layer<S> for CountErrors {
+            fn on_event(
+                &self,
+                event: &tracing::Event<'_>,
+                _: tracing_subscriber::layer::Context<'_, S>,
+            ) {
+                if *event.metadata().level() == tracing::Level::ERROR {
+                    self.0.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        }
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let _subscriber = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(CountErrors(errors.clone())),
+        );
+
+        let waveform = Pattern::Wave.pulses_at(0.5, 50);
+        let state = Mutex::new(StreamState {
+            pulses: Pulses {
+                intensity: Stereo::symmetric(IntensityChange::RelativeIncrease(5)),
+                ..waveform
+            },
+            ramp: None,
+        });
+        let stopped = Mutex::new(true);
+        let sent = Mutex::new(Vec::new());
+
+        let interval = Interval::new(Arc::new(TokioTimer), PULSE_INTERVAL);
+        let stream = stream_pulses(&state, interval, |next| {
+            let (stopped, sent) = (&stopped, &sent);
+            async move {
+                if *stopped.lock().unwrap() {
+                    return Err(Error::EmergencyStopped);
+                }
+                sent.lock().unwrap().push(next);
+                Ok(())
+            }
+        });
+        let clear = async {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+            *stopped.lock().unwrap() = false;
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        };
+        futures::pin_mut!(stream);
+        futures::future::select(stream, std::pin::pin!(clear)).await;
+
+        // the skipped ticks are not logged as errors, and the intensity change is dropped
+        assert_eq!(errors.load(Ordering::SeqCst), 0);
+        assert_eq!(*sent.lock().unwrap(), [waveform, waveform]);
+    }
+
+    #[test]
+    fn test_pulse_interval() {
+        let default = Coyote3Builder::default();
+        assert_eq!(
+            check_pulse_interval(default.pulse_interval.unwrap_or(PULSE_INTERVAL)).ok(),
+            Some(Duration::from_millis(100))
+        );
+
+        let custom = default.pulse_interval(Duration::from_millis(60));
+        assert_eq!(
+            check_pulse_interval(custom.pulse_interval.unwrap()).ok(),
+            Some(Duration::from_millis(60))
+        );
+
+        for interval in [
+            Duration::ZERO,
+            Duration::from_millis(50),
+            Duration::from_micros(100_400),
+        ] {
+            assert!(matches!(
+                check_pulse_interval(interval),
+                Err(Error::InvalidPulseInterval(i)) if i == interval
+            ));
+        }
+    }
+
+    #[test]
+    fn test_stream_ramp() {
+        use futures::channel::oneshot;
+
+        let waveform = Pattern::Wave.pulses_at(0.5, 50);
+        let pending = Stereo::symmetric(IntensityChange::RelativeIncrease(5));
+        let mut state = StreamState {
+            pulses: Pulses {
+                intensity: pending,
+                ..waveform
+            },
+            ramp: None,
+        };
+        let ramp = |steps| {
+            let (done, ramped) = oneshot::channel();
+            let ramp = Ramp {
+                start: Stereo { a: 0, b: 20 },
+                target: Stereo { a: 30, b: 20 },
+                taken: 0,
+                steps,
+                done,
+            };
+            (ramp, ramped)
+        };
+        let tick = |state: &mut StreamState, result: Result<()>| {
+            let (next, ramp) = state.next();
+            (next.intensity, state.sent(next, ramp, result))
+        };
+        let absolute = |a| Stereo { a, b: 20 }.map(IntensityChange::AbsoluteChange);
+
+        let (first, mut ramped) = ramp(3);
+        state.ramp = Some(first);
+
+        // the pending intensity change is sent first, a paused step is taken again
+        assert!(matches!(tick(&mut state, Ok(())), (i, Ok(())) if i == pending));
+        assert!(matches!(tick(&mut state, Ok(())), (i, Ok(())) if i == absolute(10)));
+        assert!(matches!(
+            tick(&mut state, Err(Error::Paused)),
+            (i, Err(Error::Paused)) if i == absolute(20)
+        ));
+        assert!(matches!(ramped.try_recv(), Ok(None)));
+        assert!(matches!(tick(&mut state, Ok(())), (i, Ok(())) if i == absolute(20)));
+        assert!(matches!(ramped.try_recv(), Ok(None)));
+
+        // the ramp is done once its last step was sent
+        assert!(matches!(tick(&mut state, Ok(())), (i, Ok(())) if i == absolute(30)));
+        assert!(matches!(ramped.try_recv(), Ok(Some(Ok(())))));
+        assert_eq!(tick(&mut state, Ok(())).0, waveform.intensity);
+
+        // a step that can't be sent ends the ramp with the error
+        let (stopped, mut ramped) = ramp(3);
+        state.ramp = Some(stopped);
+        assert!(matches!(
+            tick(&mut state, Err(Error::EmergencyStopped)),
+            (_, Ok(()))
+        ));
+        assert!(matches!(
+            ramped.try_recv(),
+            Ok(Some(Err(Error::EmergencyStopped)))
+        ));
+        assert!(state.ramp.is_none());
+
+        // a ramp whose future was dropped takes no more steps
+        let (dropped, ramped) = ramp(3);
+        state.ramp = Some(dropped);
+        drop(ramped);
+        assert_eq!(tick(&mut state, Ok(())).0, waveform.intensity);
+        assert!(state.ramp.is_none());
+    }
+
+    #[test]
+    fn test_ramp_steps() {
+        let start = Stereo { a: 10, b: 50 };
+        let target = Stereo { a: 50, b: 0 };
+        let steps = (1..=4)
+            .map(|step| ramp_step(start, target, step, 4))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            steps,
+            [
+                Stereo { a: 20, b: 38 },
+                Stereo { a: 30, b: 25 },
+                Stereo { a: 40, b: 13 },
+                Stereo { a: 50, b: 0 },
+            ]
+        );
+        assert_eq!(ramp_step(start, target, 1, 1), target);
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let config = AutoReconnect::default();
+        let delays = (0..8).map(|attempt| config.delay(attempt).as_millis());
+        assert!(delays.eq([500, 1000, 2000, 4000, 8000, 16000, 30000, 30000]));
+        assert_eq!(config.delay(u32::MAX), Duration::from_secs(30));
+
+        let config = AutoReconnect {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5),
+            ..Default::default()
+        };
+        let delays = (0..4).map(|attempt| config.delay(attempt).as_secs());
+        assert!(delays.eq([1, 2, 4, 5]));
+    }
+
+    #[test]
+    fn test_parse_battery() {
+        assert_eq!(parse_battery(&[87]).unwrap(), 87);
+        assert!(matches!(parse_battery(&[]), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_parse_voltage() {
+        // 3.75 V
+        assert_eq!(parse_voltage(&[0xF0, 0x00]).unwrap(), 3750);
+        assert_eq!(parse_voltage(&[0xFF, 0xFF]).unwrap(), u16::MAX);
+        assert!(matches!(parse_voltage(&[0xF0]), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_service_cache() {
+        let characteristic = |uuid| Characteristic {
+            uuid,
+            service_uuid: Uuid::nil(),
+            properties: Default::default(),
+            descriptors: Default::default(),
+        };
+        let all = BTreeSet::from(
+            [
+                BATTERY_CHARACTERISTIC_UUID,
+                NOTIFY_CHARACTERISTIC_UUID,
+                WRITE_CHARACTERISTIC_UUID,
+                MODEL_NUMBER_CHARACTERISTIC_UUID,
+            ]
+            .map(characteristic),
+        );
+        let address = BDAddr::from([1, 2, 3, 4, 5, 6]);
+        let cache = ServiceCache::new();
+        assert!(cache.get(address).is_none());
+
+        cache.insert(address, all.clone());
+        let cached = cache.get(address).unwrap();
+        assert_eq!(cached.battery.uuid, BATTERY_CHARACTERISTIC_UUID);
+        assert_eq!(cached.notify.uuid, NOTIFY_CHARACTERISTIC_UUID);
+        assert_eq!(cached.write.uuid, WRITE_CHARACTERISTIC_UUID);
+        assert_eq!(cached.all, all);
+        assert!(cache.get(BDAddr::from([6, 5, 4, 3, 2, 1])).is_none());
+
+        // incomplete characteristics fall back to the discovery
+        let mut incomplete = all;
+        incomplete.remove(&characteristic(NOTIFY_CHARACTERISTIC_UUID));
+        cache.clone().insert(address, incomplete);
+        assert!(cache.get(address).is_none());
+    }
+
+    #[test]
+    fn test_parse_info_string() {
+        assert_eq!(parse_info_string(b"1.0.3"), "1.0.3");
+        assert_eq!(parse_info_string(b"47L121000\0\0"), "47L121000");
+        assert_eq!(parse_info_string(b" V3 \xff"), "V3 \u{fffd}");
+    }
+
+    #[test]
+    fn test_missing_characteristic() {
+        let characteristic = |uuid| Characteristic {
+            uuid,
+            service_uuid: Uuid::nil(),
+            properties: Default::default(),
+            descriptors: Default::default(),
+        };
+        let characteristics = BTreeSet::from([
+            characteristic(WRITE_CHARACTERISTIC_UUID),
+            characteristic(NOTIFY_CHARACTERISTIC_UUID),
+        ]);
+
+        assert_eq!(
+            find_characteristic(&characteristics, WRITE_CHARACTERISTIC_UUID)
+                .unwrap()
+                .uuid,
+            WRITE_CHARACTERISTIC_UUID
+        );
+        assert!(matches!(
+            find_characteristic(&characteristics, BATTERY_CHARACTERISTIC_UUID),
+            Err(Error::MissingCharacteristic(BATTERY_CHARACTERISTIC_UUID))
+        ));
+    }
+
+    #[test]
+    fn test_scan_filter() {
+        assert_eq!(
+            Coyote3::connect().effective_scan_filter().services,
+            [uuid!("0000180C-0000-1000-8000-00805f9b34fb")]
+        );
+        assert_eq!(
+            Coyote3::connect()
+                .scan_filter(ScanFilter::default())
+                .effective_scan_filter(),
+            ScanFilter::default()
+        );
+    }
+
+    #[test]
+    fn test_scan_retries() {
+        use futures::{executor::block_on, stream};
+
+        // the first scan ends before the device is found, the second one finds it
+        let scans = [vec!["other"], vec!["other", "coyote"]];
+        let find = |retries| {
+            block_on(find_peripheral(
+                retries,
+                |attempt| std::future::ready(Ok(stream::iter(scans[attempt as usize].clone()))),
+                |name| std::future::ready(Ok((name == "coyote").then_some(name))),
+            ))
+        };
+
+        assert!(matches!(find(0), Err(Error::DeviceNotFound)));
+        assert!(matches!(find(1), Ok("coyote")));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_state() {
+        let (updates, receiver) = futures::channel::mpsc::unbounded();
+        let state = track_state(receiver, State::default(), true, Span::none());
+
+        updates
+            .unbounded_send(Update::Intensity(Stereo { a: 0, b: 20 }))
+            .unwrap();
+        updates
+            .unbounded_send(Update::Settings(DeviceSettings {
+                limit: Stereo { a: 50, b: 100 },
+                ..Default::default()
+            }))
+            .unwrap();
+        tokio::task::yield_now().await;
+
+        // the updates are reported like the device would, so the channels are swapped back
+        assert_eq!(state.get().intensity, Stereo { a: 20, b: 0 });
+        assert_eq!(state.get().settings.limit, Stereo { a: 100, b: 50 });
+    }
+
+    #[test]
+    fn test_unchanged_notifications_are_skipped() {
+        let notifications = [
+            (BATTERY_CHARACTERISTIC_UUID, vec![85]),
+            (NOTIFY_CHARACTERISTIC_UUID, hex!("b1001428").to_vec()),
+            (NOTIFY_CHARACTERISTIC_UUID, hex!("bec8c8a0a00000").to_vec()),
+        ];
+        let mut state = State::default();
+
+        let changes = (0..10_000)
+            .filter(|i| {
+                let (uuid, value) = notifications[i % notifications.len()].clone();
+                state.apply(Update::Notification(ValueNotification { uuid, value }))
+            })
+            .count();
+
+        // only the first of each changes the state
+        assert_eq!(changes, notifications.len());
+        assert_eq!(state.battery, 85);
+        assert_eq!(state.intensity, Stereo { a: 20, b: 40 });
+        assert_eq!(state.settings.limit, Stereo { a: 200, b: 200 });
+    }
+
+    #[test]
+    fn test_malformed_battery_notification() {
+        let mut state = State {
+            battery: 85,
+            ..Default::default()
+        };
+
+        assert!(!state.apply(Update::Notification(ValueNotification {
+            uuid: BATTERY_CHARACTERISTIC_UUID,
+            value: vec![],
+        })));
+        assert_eq!(state.battery, 85);
+    }
+
+    /// Feeds 10k notifications into a state, setting it either on every notification or only
+    /// when it changed. Run with `cargo test --release --lib -- --ignored --nocapture bench_`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_notifications() {
+        const COUNT: u32 = 10_000;
+
+        let notifications = [
+            (BATTERY_CHARACTERISTIC_UUID, vec![85]),
+            (NOTIFY_CHARACTERISTIC_UUID, hex!("b1001428").to_vec()),
+            (NOTIFY_CHARACTERISTIC_UUID, hex!("bec8c8a0a00000").to_vec()),
+        ];
+        let measure = |name: &str, every_notification: bool| {
+            let updates = (0..COUNT as usize).map(|i| {
+                let (uuid, value) = notifications[i % notifications.len()].clone();
+                Update::Notification(ValueNotification { uuid, value })
+            });
+            let updates = updates.collect::<Vec<_>>();
+            let (source, state) = DeviceState::new(State::default());
+            let mut device = State::default();
+
+            let start = Instant::now();
+            for update in updates {
+                if device.apply(update) || every_notification {
+                    source.set(device);
+                }
+            }
+            let elapsed = start.elapsed();
+
+            assert_eq!(state.get(), device);
+            println!("{name}: {:?} per notification", elapsed / COUNT);
+        };
+
+        measure("set on every notification", true);
+        measure("set on changes only", false);
+    }
+
+    #[tokio::test]
+    async fn test_independent_states() {
+        let (first_updates, first) = futures::channel::mpsc::unbounded();
+        let (second_updates, second) = futures::channel::mpsc::unbounded();
+        let initial = State {
+            battery: 100,
+            connected: true,
+            ..Default::default()
+        };
+        let first = track_state(first, initial, false, Span::none());
+        let second = track_state(second, initial, false, Span::none());
+
+        first_updates
+            .unbounded_send(Update::Notification(ValueNotification {
+                uuid: NOTIFY_CHARACTERISTIC_UUID,
+                value: hex!("b1001428").to_vec(),
+            }))
+            .unwrap();
+        second_updates
+            .unbounded_send(Update::Connected(false))
+            .unwrap();
+        tokio::task::yield_now().await;
+
+        assert_eq!(first.get().intensity, Stereo { a: 20, b: 40 });
+        assert!(first.get().connected);
+        assert_eq!(second.get().intensity, Stereo { a: 0, b: 0 });
+        assert!(!second.get().connected);
+    }
+
+    #[test]
+    fn test_emergency_commands() {
+        let settings = DeviceSettings {
+            limit: Stereo { a: 50, b: 100 },
+            ..Default::default()
+        };
+        let (pulses, settings) = emergency_commands(settings);
+
+        assert_eq!(
+            &*Command::SendPulses(pulses).to_bytes().unwrap(),
+            hex!("b00f0000 00000000 00000000 00000000 00000000")
+        );
+        assert_eq!(
+            &*Command::UpdateSettings(settings).to_bytes().unwrap(),
+            hex!("bf0000a0a00000")
+        );
+    }
+
+    #[test]
+    fn test_emergency_stop_blocks() {
+        let mut emergency = EmergencyStop::default();
+        assert!(emergency.check().is_ok());
+
+        emergency.engaged = true;
+        assert!(matches!(emergency.check(), Err(Error::EmergencyStopped)));
+
+        emergency.engaged = false;
+        assert!(emergency.check().is_ok());
+    }
+
+    #[test]
+    fn test_progress_callback() {
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let builder = Coyote3Builder::default().on_progress({
+            let reported = reported.clone();
+            move |progress| reported.lock().unwrap().push(progress)
+        });
+        let steps = [
+            ConnectProgress::Scanning,
+            ConnectProgress::Found(BDAddr::default()),
+            ConnectProgress::Connecting,
+            ConnectProgress::DiscoveringServices,
+            ConnectProgress::Subscribing,
+            ConnectProgress::Ready,
+        ];
+
+        // clones of the builder share the callback
+        let progress = builder.clone().on_progress;
+        for step in steps {
+            progress.report(step);
+        }
+        assert_eq!(*reported.lock().unwrap(), steps);
+        assert!(format!("{builder:?}").contains("ProgressCallback(Some(\"..\"))"));
+
+        // without a callback, nothing happens
+        ProgressCallback::default().report(ConnectProgress::Ready);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut pause = Pause::default();
+        assert_eq!(pause.resume(), Pulses::silent());
+
+        let pulses = WaveformBuilder::new()
+            .intensity(Stereo::symmetric(IntensityChange::RelativeIncrease(5)))
+            .a(Frequency::hz(100).unwrap(), Envelope::Constant(50))
+            .build();
+        pause.record(pulses);
+        assert!(pause.check().is_ok());
+
+        // paused, the output is silenced and pulses are blocked
+        assert_eq!(pause.pause(), Pulses::silent());
+        assert!(matches!(pause.check(), Err(Error::Paused)));
+
+        // resumed with the waveform, without applying the intensity change again
+        assert_eq!(
+            pause.resume(),
+            Pulses {
+                intensity: Pulses::silent().intensity,
+                ..pulses
+            }
+        );
+        assert!(pause.check().is_ok());
+    }
+
+    #[test]
+    fn test_jitter() {
+        let start = Instant::now();
+        let mut times = SendTimes::default();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        times.record(at(0));
+        assert_eq!(times.last, Some(at(0)));
+        assert_eq!(times.jitter(PULSE_INTERVAL), None);
+
+        // 100 ms, 130 ms and 70 ms apart
+        for ms in [100, 230, 300] {
+            times.record(at(ms));
+        }
+        assert_eq!(times.last, Some(at(300)));
+        assert_eq!(
+            times.jitter(PULSE_INTERVAL),
+            Some(Duration::from_millis(20))
+        );
+
+        // only the last 10 intervals count
+        for i in 1..=10 {
+            times.record(at(300 + i * 100));
+        }
+        assert_eq!(times.jitter(PULSE_INTERVAL), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_swap_commands() {
+        let pulses = WaveformBuilder::new()
+            .intensity(Stereo {
+                a: IntensityChange::AbsoluteChange(10),
+                b: IntensityChange::DoNotChange,
+            })
+            .a(Frequency::hz(100).unwrap(), Envelope::Constant(50))
+            .build();
+        let settings = DeviceSettings {
+            limit: Stereo { a: 50, b: 100 },
+            ..Default::default()
+        };
+
+        assert_eq!(pulses.oriented(false), pulses);
+        assert_eq!(pulses.oriented(true).oriented(true), pulses);
+        assert_eq!(
+            &*Command::SendPulses(pulses.oriented(true))
+                .to_bytes()
+                .unwrap(),
+            hex!("b003000a00000000000000000a0a0a0a32323232")
+        );
+
+        assert_eq!(settings.oriented(true).oriented(true), settings);
+        assert_eq!(
+            &*Command::UpdateSettings(settings.oriented(true))
+                .to_bytes()
+                .unwrap(),
+            hex!("bf6432a0a00000")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_swap_notifications() {
+        let (updates, receiver) = futures::channel::mpsc::unbounded();
+        let initial = State {
+            settings: DeviceSettings {
+                limit: Stereo { a: 50, b: 100 },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let state = track_state(receiver, initial, true, Span::none());
+        let notify = |value: &[u8]| {
+            updates
+                .unbounded_send(Update::Notification(ValueNotification {
+                    uuid: NOTIFY_CHARACTERISTIC_UUID,
+                    value: value.to_vec(),
+                }))
+                .unwrap();
+            tokio::task::yield_now()
+        };
+
+        assert_eq!(state.get(), initial);
+
+        // the device reports channel B, which is channel A for the user
+        notify(&hex!("b1000014")).await;
+        assert_eq!(state.get().intensity, Stereo { a: 20, b: 0 });
+
+        notify(&hex!("be6432a0a00000")).await;
+        assert_eq!(state.get().settings.limit, Stereo { a: 50, b: 100 });
+    }
+
+    #[test]
+    fn test_uniform_pulses() {
+        let intensity = Stereo {
+            a: IntensityChange::RelativeIncrease(5),
+            b: IntensityChange::DoNotChange,
+        };
+        let pulse = Stereo {
+            a: Pulse::new(Frequency::hz(100).unwrap(), Amplitude::from(30)),
+            b: Pulse::new(Frequency::hz(20).unwrap(), Amplitude::MAX),
+        };
+
+        assert_eq!(
+            Command::SendPulses(Pulses::uniform(intensity, pulse))
+                .to_bytes()
+                .unwrap()
+                .to_vec(),
+            Command::SendPulses(Pulses {
+                intensity,
+                pulses: [pulse, pulse, pulse, pulse],
+            })
+            .to_bytes()
+            .unwrap()
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_pulse_wire_layout() {
+        let pulses = Stereo {
+            a: Pulse::new(Frequency::hz(30).unwrap(), Amplitude::from(5)),
+            b: Pulse::new(Frequency::period_ms(200.0).unwrap(), Amplitude::MAX),
+        };
+
+        assert_eq!(pulses.to_wire(), hex!("21057864"));
+
+        // the same bytes make up one column of the packet
+        let bytes = Command::SendPulses(Pulses {
+            pulses: [pulses, Stereo::symmetric(Pulse::silent()), pulses, pulses],
+            ..Pulses::silent()
+        })
+        .to_bytes()
+        .unwrap();
+        let column = |index: usize| [0, 4, 8, 12].map(|offset| bytes[4 + offset + index]);
+        assert_eq!(column(0), pulses.to_wire());
+        assert_eq!(column(1), [0; 4]);
+        assert_eq!(column(3), pulses.to_wire());
+    }
+
+    #[test]
+    fn test_default_settings_command() {
+        assert_eq!(
+            &*Command::UpdateSettings(DeviceSettings::default())
+                .to_bytes()
+                .unwrap(),
+            hex!("bf4646a0a00000")
+        );
+    }
+
+    #[test]
+    fn test_encode_commands() {
+        let pulses = Pulses {
+            intensity: Stereo {
+                a: IntensityChange::AbsoluteChange(10),
+                b: IntensityChange::AbsoluteChange(0),
+            },
+            pulses: [Stereo {
+                a: Pulse {
+                    frequency: Frequency::hz(100).unwrap(),
+                    intensity: Amplitude::ZERO,
+                },
+                b: Pulse {
+                    frequency: Frequency::hz(30).unwrap(),
+                    intensity: Amplitude::ZERO,
+                },
+            }; 4],
+        };
+        assert_eq!(
+            encode_send_pulses(&pulses),
+            hex!("b00f0a000a0a0a0a000000002121212100000000")
+        );
+
+        let settings = DeviceSettings {
+            limit: Stereo { a: 200, b: 200 },
+            frequency_balance: Stereo { a: 160, b: 160 },
+            intensity_balance: Stereo { a: 0, b: 0 },
+        };
+        assert_eq!(encode_update_settings(&settings), hex!("bfc8c8a0a00000"));
+        assert_eq!(
+            encode_update_settings(&settings)[..],
+            *Command::UpdateSettings(settings).to_bytes().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_pulses_write_into() {
+        let pulses = WaveformBuilder::new()
+            .intensity(Stereo::symmetric(IntensityChange::AbsoluteChange(10)))
+            .a(Frequency::hz(100).unwrap(), Envelope::Constant(50))
+            .build();
+
+        let mut buf = [0xFF; 32];
+        assert_eq!(pulses.write_into(&mut buf).unwrap(), Pulses::ENCODED_LEN);
+        assert_eq!(
+            buf[..Pulses::ENCODED_LEN],
+            *Command::SendPulses(pulses).to_bytes().unwrap()
+        );
+        // the rest of the buffer is not touched
+        assert_eq!(buf[Pulses::ENCODED_LEN..], [0xFF; 12]);
+
+        let mut short = [0; Pulses::ENCODED_LEN - 1];
+        assert!(matches!(
+            pulses.write_into(&mut short),
+            Err(Error::BufferTooSmall {
+                needed: Pulses::ENCODED_LEN
+            })
+        ));
+        assert_eq!(short, [0; Pulses::ENCODED_LEN - 1]);
+    }
+
+    #[test]
+    fn test_decode_command() {
+        let pulses = Pulses {
+            intensity: Stereo {
+                a: IntensityChange::RelativeIncrease(5),
+                b: IntensityChange::AbsoluteChange(0),
+            },
+            pulses: std::array::from_fn(|i| Stereo {
+                a: Pulse::new(Frequency::hz(100).unwrap(), Amplitude::from(25 * i as u8)),
+                b: Pulse::silent(),
+            }),
+        };
+        assert_eq!(
+            decode_command(&encode_send_pulses(&pulses)).unwrap(),
+            DecodedCommand::SendPulses { serial: 0, pulses }
+        );
+        assert_eq!(
+            decode_command(&hex!("b0ff0a000a0a0a0a000000002121212100000000")).unwrap(),
+            DecodedCommand::SendPulses {
+                serial: 15,
+                pulses: Pulses::uniform(
+                    Stereo {
+                        a: IntensityChange::AbsoluteChange(10),
+                        b: IntensityChange::AbsoluteChange(0),
+                    },
+                    Stereo {
+                        a: Pulse::new(Frequency::hz(100).unwrap(), Amplitude::ZERO),
+                        b: Pulse::new(Frequency::from_compressed(0x21).unwrap(), Amplitude::ZERO),
+                    }
+                )
+            }
+        );
+
+        let settings = DeviceSettings::safe();
+        assert_eq!(
+            decode_command(&encode_update_settings(&settings)).unwrap(),
+            DecodedCommand::UpdateSettings(settings)
+        );
+
+        // unknown, truncated and unsupported values
+        for bytes in [
+            &hex!("b1000000")[..],
+            &hex!("b00f0a00")[..],
+            &hex!("b00f0a00f1f1f1f1000000002121212100000000")[..],
+            &hex!("b00f0a000a0a0a0a650000002121212100000000")[..],
+        ] {
+            assert!(matches!(decode_command(bytes), Err(Error::Protocol(_))));
+        }
+    }
+
+    #[test]
+    fn test_encode_notification() {
+        for notification in [
+            ParsedNotification::IntensityChange {
+                serial: 3,
+                intensity: Stereo { a: 20, b: 5 },
+            },
+            ParsedNotification::DeviceSettingsChange(DeviceSettings::safe()),
+        ] {
+            assert_eq!(
+                parse_notification(&encode_notification(&notification)).unwrap(),
+                notification
+            );
+        }
+    }
+
+    #[test]
+    fn test_bf_command() {
+        assert_eq!(
+            &*Command::UpdateSettings(DeviceSettings {
+                limit: Stereo { a: 200, b: 200 },
+                frequency_balance: Stereo { a: 160, b: 160 },
+                intensity_balance: Stereo { a: 0, b: 0 },
+            })
+            .to_bytes()
+            .unwrap(),
+            hex!("bfc8c8a0a00000")
+        );
+    }
+}
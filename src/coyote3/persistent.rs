@@ -0,0 +1,244 @@
+use futures::{Stream, stream};
+use futures_signals::signal::SignalExt;
+use tracing::{debug, error};
+
+use std::sync::Arc;
+
+use super::{AutoReconnect, Coyote3};
+use crate::{Result, StateSignal, Timer, core::sleep};
+
+/// An event of a connection made with [`Coyote3::connect_persistent()`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ConnectionEvent<D = Coyote3> {
+    /// A connection was made. The device stays usable until [`Disconnected`] is emitted.
+    ///
+    /// [`Disconnected`]: ConnectionEvent::Disconnected
+    Connected(D),
+    /// The connection was lost.
+    Disconnected,
+    /// Connecting failed or the connection was lost, the next attempt is made after the delay
+    /// configured by [`AutoReconnect`].
+    Reconnecting,
+}
+
+/// A connection whose loss can be awaited, so [`persistent()`] can be tested without a device.
+pub(super) trait Connection: Clone + Send + 'static {
+    /// Wait until the connection is lost.
+    fn lost(&self) -> impl Future<Output = ()> + Send + 'static;
+}
+
+impl Connection for Coyote3 {
+    fn lost(&self) -> impl Future<Output = ()> + Send + 'static {
+        let connected = self.state.derive(|state| state.connected);
+
+        async move {
+            if connected.get() {
+                connected.wait_for(false).await;
+            }
+        }
+    }
+}
+
+enum Phase<D> {
+    /// Connect, after waiting for the delay of the given reconnection attempt if there is one.
+    Connect {
+        attempt: Option<u32>,
+        last: Option<D>,
+    },
+    Connected(D),
+    Lost(D),
+}
+
+/// Keep connecting using `connect`, which is given the last connection if there was one,
+/// waiting for the reconnection delays using `timer`.
+pub(super) fn persistent<C, D, F>(
+    connect: C,
+    config: AutoReconnect,
+    timer: Arc<dyn Timer>,
+) -> impl Stream<Item = ConnectionEvent<D>> + Send + use<C, D, F>
+where
+    C: FnMut(Option<&D>) -> F + Send + 'static,
+    D: Connection,
+    F: Future<Output = Result<D>> + Send,
+{
+    let phase = Phase::Connect {
+        attempt: None,
+        last: None,
+    };
+
+    stream::unfold(
+        (connect, phase, timer),
+        move |(mut connect, phase, timer)| async move {
+            let (event, phase) = match phase {
+                Phase::Connect { attempt, last } => {
+                    if let Some(attempt) = attempt {
+                        sleep(&*timer, config.delay(attempt)).await;
+                    }
+
+                    match connect(last.as_ref()).await {
+                        Ok(device) => (
+                            ConnectionEvent::Connected(device.clone()),
+                            Phase::Connected(device),
+                        ),
+                        Err(e) => {
+                            error!(?e, attempt, "failed to connect");
+
+                            let attempt = attempt.map_or(0, |attempt| attempt + 1);
+                            if config.max_attempts.is_some_and(|max| attempt >= max) {
+                                error!("giving up reconnecting after {attempt} attempts");
+                                return None;
+                            }
+
+                            (
+                                ConnectionEvent::Reconnecting,
+                                Phase::Connect {
+                                    attempt: Some(attempt),
+                                    last,
+                                },
+                            )
+                        }
+                    }
+                }
+                Phase::Connected(device) => {
+                    device.lost().await;
+                    debug!("connection lost, reconnecting");
+
+                    (ConnectionEvent::Disconnected, Phase::Lost(device))
+                }
+                Phase::Lost(device) => (
+                    ConnectionEvent::Reconnecting,
+                    Phase::Connect {
+                        attempt: Some(0),
+                        last: Some(device),
+                    },
+                ),
+            };
+
+            Some((event, (connect, phase, timer)))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use futures::{
+        FutureExt, StreamExt,
+        channel::oneshot,
+        future::{BoxFuture, Shared},
+    };
+
+    use super::*;
+    use crate::{Error, TokioTimer};
+
+    #[derive(Clone, Debug)]
+    struct FakeConnection {
+        id: u32,
+        lost: Shared<oneshot::Receiver<()>>,
+    }
+
+    impl Connection for FakeConnection {
+        fn lost(&self) -> impl Future<Output = ()> + Send + 'static {
+            self.lost.clone().map(|_| ())
+        }
+    }
+
+    /// Connect by taking the next result of `results`, recording the id of the last connection
+    /// in `calls`.
+    fn fake_connect(
+        results: Vec<Result<FakeConnection>>,
+        calls: Arc<Mutex<Vec<Option<u32>>>>,
+    ) -> impl FnMut(Option<&FakeConnection>) -> BoxFuture<'static, Result<FakeConnection>> {
+        let mut results = VecDeque::from(results);
+
+        move |last: Option<&FakeConnection>| {
+            calls.lock().unwrap().push(last.map(|last| last.id));
+            let result = results.pop_front().expect("unexpected connection attempt");
+            async move { result }.boxed()
+        }
+    }
+
+    fn describe(event: &ConnectionEvent<FakeConnection>) -> String {
+        match event {
+            ConnectionEvent::Connected(connection) => format!("connected {}", connection.id),
+            ConnectionEvent::Disconnected => "disconnected".to_owned(),
+            ConnectionEvent::Reconnecting => "reconnecting".to_owned(),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_event_order() {
+        // the first connection is lost right away, the second one stays
+        let (lose_first, first_lost) = oneshot::channel();
+        let (_keep_second, second_lost) = oneshot::channel();
+        drop(lose_first);
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let connect = fake_connect(
+            vec![
+                Err(Error::DeviceNotFound),
+                Ok(FakeConnection {
+                    id: 1,
+                    lost: first_lost.shared(),
+                }),
+                Ok(FakeConnection {
+                    id: 2,
+                    lost: second_lost.shared(),
+                }),
+            ],
+            calls.clone(),
+        );
+
+        let start = tokio::time::Instant::now();
+        let events = persistent(connect, AutoReconnect::default(), Arc::new(TokioTimer))
+            .take(5)
+            .map(|event| describe(&event))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            events,
+            [
+                "reconnecting",
+                "connected 1",
+                "disconnected",
+                "reconnecting",
+                "connected 2"
+            ]
+        );
+        assert_eq!(*calls.lock().unwrap(), [None, None, Some(1)]);
+        // one initial delay after the failed attempt and one after the lost connection
+        assert_eq!(start.elapsed(), Duration::from_millis(1000));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_give_up() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let connect = fake_connect(
+            vec![
+                Err(Error::DeviceNotFound),
+                Err(Error::DeviceNotFound),
+                Err(Error::DeviceNotFound),
+            ],
+            calls.clone(),
+        );
+        let config = AutoReconnect {
+            max_attempts: Some(2),
+            ..Default::default()
+        };
+
+        let events = persistent(connect, config, Arc::new(TokioTimer))
+            .map(|event| describe(&event))
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(events, ["reconnecting", "reconnecting"]);
+        assert_eq!(calls.lock().unwrap().len(), 3);
+    }
+}
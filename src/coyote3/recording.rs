@@ -0,0 +1,200 @@
+//! Recording the pulses sent to a Coyote 3 into a file and playing them back, see
+//! [`Coyote3::record()`](super::Coyote3::record).
+//!
+//! A recording starts with the magic bytes `DGLR` and a format version byte, currently 1. The
+//! version is only raised for changes older readers can't handle, recordings with a version that
+//! is not known are rejected.
+//!
+//! The header is followed by the frames, each made up of
+//!
+//! - its length in bytes as a single byte, not counting the length byte itself,
+//! - the time since the first frame in milliseconds as a big-endian `u32` and
+//! - the pulses, encoded like the command that sends them, see [`Pulses::write_into()`].
+//!
+//! Readers skip any bytes of a frame after the pulses, so fields can be appended to the frames
+//! without raising the version.
+
+use std::{
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+use super::{DecodedCommand, Pulses, decode_command};
+use crate::{Result, Timer};
+
+const MAGIC: &[u8; 4] = b"DGLR";
+const VERSION: u8 = 1;
+/// The length of a frame written by this version, without the length byte.
+const FRAME_LEN: usize = 4 + Pulses::ENCODED_LEN;
+
+/// Writes the pulses sent to a device to `output`.
+#[derive(Debug)]
+pub(super) struct Recorder<W: Write> {
+    output: W,
+    /// The time the first pulses were recorded at.
+    start: Option<Instant>,
+}
+
+impl<W: Write> Recorder<W> {
+    pub(super) fn new(mut output: W) -> io::Result<Self> {
+        output.write_all(MAGIC)?;
+        output.write_all(&[VERSION])?;
+
+        Ok(Self {
+            output,
+            start: None,
+        })
+    }
+    /// Record `pulses` sent at `at`.
+    pub(super) fn record(&mut self, at: Instant, pulses: &Pulses) -> io::Result<()> {
+        let start = *self.start.get_or_insert(at);
+        let time = at.saturating_duration_since(start).as_millis();
+
+        let mut frame = [0; 1 + FRAME_LEN];
+        frame[0] = FRAME_LEN as u8;
+        frame[1..5].copy_from_slice(&u32::try_from(time).unwrap_or(u32::MAX).to_be_bytes());
+        pulses
+            .write_into(&mut frame[5..])
+            .expect("the frame has room for the pulses");
+
+        self.output.write_all(&frame)
+    }
+    /// Flush the recording, returning the output.
+    pub(super) fn finish(mut self) -> io::Result<W> {
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+/// Parse a recording into the pulses and their time since the first frame.
+pub(super) fn parse_recording(bytes: &[u8]) -> io::Result<Vec<(Duration, Pulses)>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let Some((header, mut rest)) = bytes.split_at_checked(MAGIC.len() + 1) else {
+        return Err(invalid("recording is too short"));
+    };
+    if header[..MAGIC.len()] != *MAGIC {
+        return Err(invalid("not a recording"));
+    }
+    let version = header[MAGIC.len()];
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported recording version {version}")));
+    }
+
+    let mut frames = Vec::new();
+    while let Some((&len, after)) = rest.split_first() {
+        let Some((frame, after)) = after.split_at_checked(len.into()) else {
+            return Err(invalid("truncated frame"));
+        };
+        if frame.len() < FRAME_LEN {
+            return Err(invalid("frame is too short"));
+        }
+
+        let time = u32::from_be_bytes(frame[..4].try_into().unwrap());
+        let pulses = match decode_command(&frame[4..FRAME_LEN]) {
+            Ok(DecodedCommand::SendPulses { pulses, .. }) => pulses,
+            Ok(_) => return Err(invalid("frame does not contain pulses")),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        frames.push((Duration::from_millis(time.into()), pulses));
+        rest = after;
+    }
+
+    Ok(frames)
+}
+
+/// Send each of the recorded `frames` once its time since the start has passed.
+pub(super) async fn play_recording<F: Future<Output = Result<()>>>(
+    frames: impl IntoIterator<Item = (Duration, Pulses)>,
+    timer: &dyn Timer,
+    mut send: impl FnMut(Pulses) -> F,
+) -> Result<()> {
+    let start = timer.now();
+
+    for (time, pulses) in frames {
+        timer.sleep_until(start + time).await;
+        send(pulses).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        Stereo, TokioTimer,
+        coyote3::{IntensityChange, Pattern},
+    };
+
+    fn frames() -> Vec<(Duration, Pulses)> {
+        [
+            (0, Pattern::Pulse),
+            (100, Pattern::Wave),
+            (250, Pattern::Ramp),
+        ]
+        .into_iter()
+        .map(|(time, pattern)| {
+            let pulses = Pulses {
+                intensity: Stereo::symmetric(IntensityChange::RelativeIncrease(2)),
+                ..pattern.pulses_at(0.5, 50)
+            };
+            (Duration::from_millis(time), pulses)
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let start = Instant::now() + Duration::from_secs(1);
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        for (time, pulses) in frames() {
+            recorder.record(start + time, &pulses).unwrap();
+        }
+        let bytes = recorder.finish().unwrap();
+
+        assert_eq!(bytes[..5], *b"DGLR\x01");
+        assert_eq!(bytes.len(), 5 + 3 * 25);
+        assert_eq!(parse_recording(&bytes).unwrap(), frames());
+    }
+
+    #[test]
+    fn test_parse_recording() {
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        recorder.record(Instant::now(), &Pulses::silent()).unwrap();
+        let bytes = recorder.finish().unwrap();
+
+        // fields appended by a later version are skipped
+        let mut extended = bytes.clone();
+        extended[5] += 2;
+        extended.extend([0xAB, 0xCD]);
+        assert_eq!(
+            parse_recording(&extended).unwrap(),
+            [(Duration::ZERO, Pulses::silent())]
+        );
+
+        let mut unknown_version = bytes.clone();
+        unknown_version[4] = 2;
+        assert!(parse_recording(&unknown_version).is_err());
+        assert!(parse_recording(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse_recording(b"DGL").is_err());
+        assert!(parse_recording(b"WAV\x00\x01").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_play_recording() {
+        let sent = Mutex::new(Vec::new());
+        let start = tokio::time::Instant::now();
+
+        play_recording(frames(), &TokioTimer, |pulses| {
+            sent.lock().unwrap().push((start.elapsed(), pulses));
+            std::future::ready(Ok(()))
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(sent.into_inner().unwrap(), frames());
+    }
+}
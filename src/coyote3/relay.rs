@@ -0,0 +1,241 @@
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use futures_signals::signal::SignalExt;
+use tracing::warn;
+
+use super::{
+    DecodedCommand, DeviceSettings, ParsedNotification, Pulses, State, decode_command,
+    encode_notification,
+};
+use crate::{Device, Error, Result, StateSignal};
+
+/// Relay `device` over a framed transport, e.g. a WebSocket connection, so a remote client can
+/// drive it.
+///
+/// Each frame received from `incoming` is a command in the format the Coyote 3 expects on its
+/// write characteristic, see [`decode_command()`]. Pulses are forwarded using
+/// [`Device::send_pulses()`] and settings using [`Device::update_settings()`], so the checks of
+/// the device, like its [safety threshold](super::Coyote3Builder::safety_threshold), still
+/// apply. Malformed frames are logged and skipped, and so are commands the device rejects, e.g.
+/// settings it doesn't support or pulses during an
+/// [emergency stop](super::Coyote3::emergency_stop).
+///
+/// The state of the device is reported on `outgoing` in the format of its notifications, see
+/// [`parse_notification()`](super::parse_notification): an intensity change with serial 0
+/// whenever the intensity changes and a settings change whenever the settings change, starting
+/// with the current state. The battery level and the signal strength have no notification, so
+/// they are not reported.
+///
+/// The relay runs until `incoming` ends or `outgoing` fails, the output is not stopped
+/// afterwards. Use [`Device::guard()`] for that.
+///
+/// ```ignore
+/// let (write, read) = tokio_tungstenite::accept_async(stream).await?.split();
+/// let incoming = read.filter_map(|msg| async move { Some(msg.ok()?.into_data().to_vec()) });
+/// let outgoing = write.with(|frame: Vec<u8>| async move { Ok(Message::binary(frame)) });
+///
+/// relay(&coyote, incoming, outgoing).await?;
+/// ```
+pub async fn relay<D, Si>(
+    device: &D,
+    incoming: impl Stream<Item = Vec<u8>>,
+    outgoing: Si,
+) -> Result<()>
+where
+    D: Device<State = State, Pulses = Pulses, Settings = DeviceSettings>,
+    Si: Sink<Vec<u8>>,
+    Si::Error: std::error::Error + Send + Sync + 'static,
+{
+    let signal = device.state();
+    let mut reported = signal.get();
+    let mut incoming = std::pin::pin!(incoming.fuse());
+    let mut states = std::pin::pin!(signal.to_stream().fuse());
+    let mut outgoing = std::pin::pin!(outgoing);
+
+    report(&mut outgoing, None, reported).await?;
+    loop {
+        futures::select! {
+            frame = incoming.next() => match frame {
+                Some(frame) => forward(device, &frame).await,
+                None => return Ok(()),
+            },
+            state = states.select_next_some() => {
+                report(&mut outgoing, Some(reported), state).await?;
+                reported = state;
+            }
+        }
+    }
+}
+
+/// Send the notifications that report the changes from `reported` to `state`.
+async fn report<Si>(outgoing: &mut Si, reported: Option<State>, state: State) -> Result<()>
+where
+    Si: Sink<Vec<u8>> + Unpin,
+    Si::Error: std::error::Error + Send + Sync + 'static,
+{
+    for notification in notifications(reported, state) {
+        outgoing
+            .send(encode_notification(&notification))
+            .await
+            .map_err(|e| Error::Transport(Box::new(e)))?;
+    }
+    Ok(())
+}
+
+/// Forward a command received by the relay to `device`, logging it if it is malformed or
+/// rejected.
+async fn forward<D>(device: &D, frame: &[u8])
+where
+    D: Device<Pulses = Pulses, Settings = DeviceSettings>,
+{
+    let result = match decode_command(frame) {
+        Ok(DecodedCommand::SendPulses { pulses, .. }) => device.send_pulses(pulses).await,
+        Ok(DecodedCommand::UpdateSettings(settings)) => device.update_settings(settings).await,
+        Err(e) => {
+            warn!(error = %e, "skipping malformed frame");
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        warn!(error = %e, "device rejected relayed command");
+    }
+}
+
+/// The notifications that report the changes from `reported` to `state`.
+fn notifications(reported: Option<State>, state: State) -> Vec<ParsedNotification> {
+    let mut notifications = Vec::new();
+
+    if reported.is_none_or(|r| r.intensity != state.intensity) {
+        notifications.push(ParsedNotification::IntensityChange {
+            serial: 0,
+            intensity: state.intensity,
+        });
+    }
+    if reported.is_none_or(|r| r.settings != state.settings) {
+        notifications.push(ParsedNotification::DeviceSettingsChange(state.settings));
+    }
+
+    notifications
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    use futures::{channel::mpsc, executor::block_on};
+
+    use super::super::{
+        IntensityChange, MockCoyote3, SentCommand, encode_send_pulses, encode_update_settings,
+        parse_notification,
+    };
+    use crate::Stereo;
+
+    #[test]
+    fn test_relay_round_trip() {
+        let coyote = MockCoyote3::new();
+        let (mut to_relay, incoming) = mpsc::unbounded::<Vec<u8>>();
+        let (outgoing, mut from_relay) = mpsc::unbounded::<Vec<u8>>();
+
+        let pulses = Pulses {
+            intensity: Stereo {
+                a: IntensityChange::AbsoluteChange(20),
+                b: IntensityChange::DoNotChange,
+            },
+            ..Pulses::silent()
+        };
+        let settings = DeviceSettings {
+            limit: Stereo { a: 50, b: 30 },
+            ..Default::default()
+        };
+
+        let client = async {
+            let mut next = async || parse_notification(&from_relay.next().await.unwrap()).unwrap();
+
+            // the current state is reported first
+            assert_eq!(
+                next().await,
+                ParsedNotification::IntensityChange {
+                    serial: 0,
+                    intensity: Stereo { a: 0, b: 0 }
+                }
+            );
+            assert_eq!(
+                next().await,
+                ParsedNotification::DeviceSettingsChange(Default::default())
+            );
+
+            to_relay.send(vec![0xB0, 0x01]).await.unwrap();
+            to_relay
+                .send(encode_send_pulses(&pulses).to_vec())
+                .await
+                .unwrap();
+            assert_eq!(
+                next().await,
+                ParsedNotification::IntensityChange {
+                    serial: 0,
+                    intensity: Stereo { a: 20, b: 0 }
+                }
+            );
+
+            to_relay
+                .send(encode_update_settings(&settings).to_vec())
+                .await
+                .unwrap();
+            assert_eq!(
+                next().await,
+                ParsedNotification::DeviceSettingsChange(settings)
+            );
+
+            to_relay.close_channel();
+        };
+
+        let (relayed, ()) = block_on(futures::future::join(
+            relay(&coyote, incoming, outgoing),
+            client,
+        ));
+        relayed.unwrap();
+
+        // the malformed frame was skipped
+        assert_eq!(
+            coyote.sent(),
+            [SentCommand::Pulses(pulses), SentCommand::Settings(settings)]
+        );
+    }
+
+    #[test]
+    fn test_rejected_commands() {
+        let coyote = MockCoyote3::new();
+        let (mut to_relay, incoming) = mpsc::unbounded::<Vec<u8>>();
+        let (outgoing, _from_relay) = mpsc::unbounded::<Vec<u8>>();
+
+        let over_limit = DeviceSettings {
+            limit: Stereo { a: 250, b: 30 },
+            ..Default::default()
+        };
+        let settings = DeviceSettings {
+            limit: Stereo { a: 50, b: 30 },
+            ..Default::default()
+        };
+
+        let client = async {
+            to_relay
+                .send(encode_update_settings(&over_limit).to_vec())
+                .await
+                .unwrap();
+            to_relay
+                .send(encode_update_settings(&settings).to_vec())
+                .await
+                .unwrap();
+            to_relay.close_channel();
+        };
+
+        let (relayed, ()) = block_on(futures::future::join(
+            relay(&coyote, incoming, outgoing),
+            client,
+        ));
+        relayed.unwrap();
+
+        // the relay kept going after the device rejected the first settings
+        assert_eq!(coyote.sent(), [SentCommand::Settings(settings)]);
+    }
+}
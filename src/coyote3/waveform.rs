@@ -0,0 +1,540 @@
+use crate::Stereo;
+
+use super::{Amplitude, Frequency, IntensityChange, Pulse, Pulses};
+
+/// How the amplitude of a channel develops over the four 25 ms pulses of a [`Pulses`] frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Envelope {
+    /// Keep the same amplitude for all pulses.
+    Constant(u8),
+    /// Linearly ramp from the amplitude `from` for the first pulse to `to` for the last pulse.
+    Linear {
+        /// The amplitude of the first pulse.
+        from: u8,
+        /// The amplitude of the last pulse.
+        to: u8,
+    },
+}
+
+impl Envelope {
+    fn amplitude(&self, index: usize) -> u8 {
+        match *self {
+            Envelope::Constant(amplitude) => amplitude,
+            Envelope::Linear { from, to } => {
+                let (from, to) = (from as i32, to as i32);
+                (from + (to - from) * index as i32 / 3) as u8
+            }
+        }
+    }
+}
+
+/// Builds the [`Pulses`] for a single 100 ms frame from a frequency and an amplitude envelope
+/// per channel.
+///
+/// Channels that are not set stay silent and the intensity is not changed unless set using
+/// [`intensity()`](Self::intensity). Amplitudes above 100 saturate at [`Amplitude::MAX`].
+///
+/// ```
+/// # use dungeonctl::coyote3::{Envelope, Frequency, WaveformBuilder};
+/// let pulses = WaveformBuilder::new()
+///     .a(Frequency::hz(100)?, Envelope::Linear { from: 0, to: 60 })
+///     .b_with(Frequency::hz(50)?, |index| [100, 0, 100, 0][index])
+///     .build();
+///
+/// assert_eq!(pulses.pulses.map(|p| p.a.intensity.value()), [0, 20, 40, 60]);
+/// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct WaveformBuilder {
+    pulses: Pulses,
+}
+
+impl Default for WaveformBuilder {
+    fn default() -> Self {
+        Self {
+            pulses: Pulses::silent(),
+        }
+    }
+}
+
+impl WaveformBuilder {
+    /// Create a builder for a silent frame.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Change the stimulation intensity along with this frame.
+    pub fn intensity(mut self, intensity: Stereo<IntensityChange>) -> Self {
+        self.pulses.intensity = intensity;
+        self
+    }
+    /// Set the frequency and the amplitude envelope of channel A.
+    pub fn a(self, frequency: Frequency, envelope: Envelope) -> Self {
+        self.a_with(frequency, |index| envelope.amplitude(index))
+    }
+    /// Set the frequency and the amplitude envelope of channel B.
+    pub fn b(self, frequency: Frequency, envelope: Envelope) -> Self {
+        self.b_with(frequency, |index| envelope.amplitude(index))
+    }
+    /// Set the frequency of channel A and compute the amplitude of each of the four
+    /// pulses, given their index, using `envelope`.
+    pub fn a_with(mut self, frequency: Frequency, envelope: impl Fn(usize) -> u8) -> Self {
+        for (index, pulse) in self.pulses.pulses.iter_mut().enumerate() {
+            pulse.a = Pulse {
+                frequency,
+                intensity: Amplitude::from(envelope(index)),
+            };
+        }
+        self
+    }
+    /// Set the frequency of channel B and compute the amplitude of each of the four
+    /// pulses, given their index, using `envelope`.
+    pub fn b_with(mut self, frequency: Frequency, envelope: impl Fn(usize) -> u8) -> Self {
+        for (index, pulse) in self.pulses.pulses.iter_mut().enumerate() {
+            pulse.b = Pulse {
+                frequency,
+                intensity: Amplitude::from(envelope(index)),
+            };
+        }
+        self
+    }
+    /// Build the pulses.
+    pub fn build(self) -> Pulses {
+        self.pulses
+    }
+}
+
+/// Builds the [`Pulses`] for a single 100 ms frame by setting each of its four 25 ms slots
+/// individually.
+///
+/// Slots that are not set stay silent and the intensity is not changed unless set using
+/// [`intensity()`](Self::intensity).
+///
+/// ```
+/// # use dungeonctl::{Stereo, coyote3::{Amplitude, Frequency, Pulse, PulsesBuilder}};
+/// // a short jolt on channel A followed by silence
+/// let pulses = PulsesBuilder::new()
+///     .slot(0, Stereo {
+///         a: Pulse::new(Frequency::hz(100)?, Amplitude::MAX),
+///         b: Pulse::silent(),
+///     })
+///     .build();
+///
+/// assert_eq!(pulses.pulses.map(|p| p.a.intensity.value()), [100, 0, 0, 0]);
+/// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct PulsesBuilder {
+    pulses: Pulses,
+}
+
+impl Default for PulsesBuilder {
+    fn default() -> Self {
+        Self {
+            pulses: Pulses::silent(),
+        }
+    }
+}
+
+impl PulsesBuilder {
+    /// Create a builder for a silent frame.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Change the stimulation intensity along with this frame.
+    pub fn intensity(mut self, intensity: Stereo<IntensityChange>) -> Self {
+        self.pulses.intensity = intensity;
+        self
+    }
+    /// Set the pulses of the slot at `index`, where 0 is the first 25 ms of the frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not in the range of 0 to 3.
+    pub fn slot(mut self, index: usize, pulse: Stereo<Pulse>) -> Self {
+        assert!(index < 4, "a frame only has four slots, got index {index}");
+        self.pulses.pulses[index] = pulse;
+        self
+    }
+    /// Build the pulses.
+    pub fn build(self) -> Pulses {
+        self.pulses
+    }
+}
+
+/// Fill the four pulses of a frame by interpolating from `start` for the first pulse to `end`
+/// for the last pulse, see [`Pulse::lerp()`].
+///
+/// This is useful for smooth transitions within a single frame.
+///
+/// ```
+/// # use dungeonctl::{Stereo, coyote3::{Amplitude, Frequency, Pulse, Pulses, interpolate}};
+/// let start = Pulse::new(Frequency::hz(100)?, Amplitude::ZERO);
+/// let end = Pulse::new(Frequency::hz(100)?, Amplitude::from(60));
+///
+/// let pulses = Pulses {
+///     pulses: interpolate(Stereo::symmetric(start), Stereo::symmetric(end)),
+///     ..Pulses::silent()
+/// };
+/// assert_eq!(pulses.pulses.map(|p| p.a.intensity.value()), [0, 20, 40, 60]);
+/// # Ok::<(), dungeonctl::coyote3::PulseError>(())
+/// ```
+pub fn interpolate(start: Stereo<Pulse>, end: Stereo<Pulse>) -> [Stereo<Pulse>; 4] {
+    std::array::from_fn(|index| {
+        let t = index as f32 / 3.0;
+        start.zip_with(end, |start, end| start.lerp(&end, t))
+    })
+}
+
+/// Group samples taken every 25 ms into [`Pulses`] frames of four pulses each, playing them on
+/// the channels enabled in `channels`.
+///
+/// Disabled channels stay silent and the intensity is not changed. If the number of samples is
+/// not a multiple of four, the last frame is padded with [silent](Pulse::silent) pulses.
+///
+/// ```
+/// # use dungeonctl::{Stereo, coyote3::{Amplitude, Frequency, frames}};
+/// // a sine envelope from a signal generator, sampled every 25 ms
+/// let envelope = (0..10).map(|i| (i as f32 * 0.3).sin().abs());
+/// let samples = envelope.map(|x| (Frequency::hz(80).unwrap(), Amplitude::from((x * 100.0) as u8)));
+///
+/// let frames = frames(samples, Stereo { a: true, b: false }).collect::<Vec<_>>();
+/// assert_eq!(frames.len(), 3);
+/// ```
+pub fn frames<I>(samples: I, channels: Stereo<bool>) -> impl Iterator<Item = Pulses> + use<I>
+where
+    I: IntoIterator<Item = (Frequency, Amplitude)>,
+{
+    let mut samples = samples.into_iter().fuse();
+
+    std::iter::from_fn(move || {
+        let first = samples.next()?;
+        let mut pulses = Pulses::silent();
+
+        let frame = std::iter::once(first).chain(samples.by_ref().take(3));
+        for ((frequency, amplitude), pulse) in frame.zip(&mut pulses.pulses) {
+            let sample = Pulse::new(frequency, amplitude);
+            *pulse = channels.map(|enabled| if enabled { sample } else { Pulse::silent() });
+        }
+
+        Some(pulses)
+    })
+}
+
+/// Combines two independent per-channel generators into [`Pulses`] frames.
+///
+/// Each generator yields one [`Pulse`] per 25 ms for its channel, so both channels can run
+/// different waveforms with their own timing. Each frame takes the next four pulses of each
+/// generator. Once a generator ends, its channel stays silent, and the sequencer ends once both
+/// generators ended. The intensity is not changed.
+///
+/// ```
+/// # use dungeonctl::coyote3::{Amplitude, Frequency, Pulse, Sequencer};
+/// // a fast buzz on channel A, a slow breath with a period of two seconds on channel B
+/// let buzz = [100, 0].map(|a| Pulse::new(Frequency::hz(200).unwrap(), Amplitude::from(a)));
+/// let breath = (0..80).map(|i| {
+///     let x = (i as f32 / 80.0 * std::f32::consts::TAU).sin().abs();
+///     Pulse::new(Frequency::hz(20).unwrap(), Amplitude::from((x * 100.0) as u8))
+/// });
+///
+/// let frames = Sequencer::new(buzz.into_iter().cycle(), breath.cycle());
+/// # assert_eq!(frames.take(5).count(), 5);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Sequencer<A, B> {
+    a: std::iter::Fuse<A>,
+    b: std::iter::Fuse<B>,
+}
+
+impl<A: Iterator<Item = Pulse>, B: Iterator<Item = Pulse>> Sequencer<A, B> {
+    /// Create a sequencer playing the pulses of `a` on channel A and the ones of `b` on
+    /// channel B.
+    pub fn new(
+        a: impl IntoIterator<IntoIter = A, Item = Pulse>,
+        b: impl IntoIterator<IntoIter = B, Item = Pulse>,
+    ) -> Self {
+        Self {
+            a: a.into_iter().fuse(),
+            b: b.into_iter().fuse(),
+        }
+    }
+}
+
+impl<A: Iterator<Item = Pulse>, B: Iterator<Item = Pulse>> Iterator for Sequencer<A, B> {
+    type Item = Pulses;
+
+    fn next(&mut self) -> Option<Pulses> {
+        let mut pulses = Pulses::silent();
+        let mut ended = true;
+
+        for pulse in &mut pulses.pulses {
+            let next = Stereo {
+                a: self.a.next(),
+                b: self.b.next(),
+            };
+            ended &= next.a.is_none() && next.b.is_none();
+            *pulse = next.map(|pulse| pulse.unwrap_or_else(Pulse::silent));
+        }
+
+        (!ended).then_some(pulses)
+    }
+}
+
+/// Waveform patterns resembling the presets of the official app.
+///
+/// A pattern is played by calling [`pulses_at()`](Self::pulses_at) every 100 ms with a phase
+/// that advances by 0.1, so one cycle of the pattern takes one second. Both channels are driven
+/// with the same waveform. Given the phase `x` within the cycle, the amplitudes follow these
+/// curves, scaled to the peak amplitude:
+///
+/// | pattern  | frequency | amplitude                                                       |
+/// |----------|-----------|-----------------------------------------------------------------|
+/// | `Pulse`  | 100 Hz    | full for `x < 0.5`, off afterwards                              |
+/// | `Wave`   | 50 Hz     | `(1 − cos(2πx)) / 2`, a sine from off to full and back          |
+/// | `Breath` | 20 Hz     | rises linearly until 0.4, holds until 0.6, falls linearly to 1  |
+/// | `Ramp`   | 100 Hz    | `x`, rises linearly and drops back to off                       |
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Pattern {
+    /// Switches between full amplitude and off.
+    Pulse,
+    /// Smoothly swells and fades.
+    Wave,
+    /// Slowly breathes in, holds and breathes out.
+    Breath,
+    /// Rises and drops back.
+    Ramp,
+}
+
+impl Pattern {
+    /// The phase covered by a single 25 ms pulse.
+    const PULSE_PHASE: f32 = 0.025;
+
+    /// Get the pulses for the 100 ms starting at `phase`, where 1.0 is one cycle of the pattern.
+    ///
+    /// `intensity` is the peak amplitude in the range of 0 to 100. The stimulation intensity of
+    /// the device is not changed.
+    ///
+    /// ```
+    /// # use dungeonctl::coyote3::Pattern;
+    /// let frames = (0..10).map(|i| Pattern::Wave.pulses_at(i as f32 * 0.1, 80));
+    /// # assert_eq!(frames.count(), 10);
+    /// ```
+    pub fn pulses_at(&self, phase: f32, intensity: u8) -> Pulses {
+        let frequency = Frequency::hz(match self {
+            Pattern::Pulse | Pattern::Ramp => 100,
+            Pattern::Wave => 50,
+            Pattern::Breath => 20,
+        })
+        .expect("pattern frequencies are in range");
+        let envelope = |index: usize| {
+            let x = (phase + index as f32 * Self::PULSE_PHASE).rem_euclid(1.0);
+            (self.amplitude(x) * intensity as f32).round() as u8
+        };
+
+        WaveformBuilder::new()
+            .a_with(frequency, envelope)
+            .b_with(frequency, envelope)
+            .build()
+    }
+    /// The relative amplitude in the range of 0.0 to 1.0 at `x` within the cycle.
+    fn amplitude(&self, x: f32) -> f32 {
+        match self {
+            Pattern::Pulse => (x < 0.5) as u8 as f32,
+            Pattern::Wave => (1.0 - (std::f32::consts::TAU * x).cos()) / 2.0,
+            Pattern::Breath => match x {
+                ..0.4 => x / 0.4,
+                ..0.6 => 1.0,
+                _ => (1.0 - x) / 0.4,
+            },
+            Pattern::Ramp => x,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hex_literal::hex;
+
+    use super::super::Command;
+
+    #[test]
+    fn test_constant_envelope() {
+        let pulses = WaveformBuilder::new()
+            .a(Frequency::hz(100).unwrap(), Envelope::Constant(40))
+            .build();
+
+        assert_eq!(
+            pulses.pulses,
+            [Stereo {
+                a: Pulse {
+                    frequency: Frequency::hz(100).unwrap(),
+                    intensity: Amplitude::from(40)
+                },
+                b: Pulse::silent(),
+            }; 4]
+        );
+        assert_eq!(pulses.intensity, Pulses::silent().intensity);
+    }
+
+    #[test]
+    fn test_sequencer() {
+        let pulse =
+            |hz, amplitude| Pulse::new(Frequency::hz(hz).unwrap(), Amplitude::from(amplitude));
+        let buzz = [pulse(200, 100), pulse(200, 0)];
+        let breath = [10, 20, 30, 40, 50, 60].map(|amplitude| pulse(20, amplitude));
+
+        let frames = Sequencer::new(buzz.into_iter().cycle(), breath)
+            .take(3)
+            .collect::<Vec<_>>();
+
+        for frame in &frames {
+            assert_eq!(
+                frame.pulses.map(|p| p.a),
+                [buzz[0], buzz[1], buzz[0], buzz[1]]
+            );
+            assert_eq!(frame.intensity, Pulses::silent().intensity);
+        }
+        assert_eq!(frames[0].pulses.map(|p| p.b), breath[..4]);
+        assert_eq!(
+            frames[1].pulses.map(|p| p.b),
+            [breath[4], breath[5], Pulse::silent(), Pulse::silent()]
+        );
+        assert_eq!(frames[2].pulses.map(|p| p.b), [Pulse::silent(); 4]);
+
+        // a frame is yielded as long as one of the channels has pulses left
+        assert_eq!(Sequencer::new(breath, buzz).count(), 2);
+        assert_eq!(Sequencer::new([], []).count(), 0);
+    }
+
+    #[test]
+    fn test_pulses_builder() {
+        let jolt = Stereo {
+            a: Pulse::new(Frequency::hz(100).unwrap(), Amplitude::MAX),
+            b: Pulse::new(Frequency::hz(50).unwrap(), Amplitude::from(60)),
+        };
+
+        let pulses = PulsesBuilder::new().slot(0, jolt).build();
+
+        assert_eq!(pulses.pulses[0], jolt);
+        assert_eq!(pulses.pulses[1..], [Stereo::symmetric(Pulse::silent()); 3]);
+        assert_eq!(pulses.intensity, Pulses::silent().intensity);
+        assert_eq!(
+            &*Command::SendPulses(pulses).to_bytes().unwrap(),
+            hex!("b0000000" "0a000000" "64000000" "14000000" "3c000000")
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_pulses_builder_index() {
+        let _ = PulsesBuilder::new().slot(4, Stereo::symmetric(Pulse::silent()));
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let start = Stereo {
+            a: Pulse::new(Frequency::period_ms(10.0).unwrap(), Amplitude::ZERO),
+            b: Pulse::new(Frequency::period_ms(40.0).unwrap(), Amplitude::MAX),
+        };
+        let end = Stereo {
+            a: Pulse::new(Frequency::period_ms(40.0).unwrap(), Amplitude::from(30)),
+            b: Pulse::silent(),
+        };
+
+        let pulses = interpolate(start, end);
+
+        assert_eq!(pulses[0], start);
+        assert_eq!(pulses[3].a, end.a);
+        // the silent pulse fades out at the frequency of the other one
+        assert_eq!(pulses[3].b, Pulse::new(start.b.frequency, Amplitude::ZERO));
+        assert_eq!(pulses.map(|p| p.a.frequency.compressed()), [10, 20, 30, 40]);
+        assert_eq!(pulses.map(|p| p.a.intensity.value()), [0, 10, 20, 30]);
+        assert_eq!(pulses.map(|p| p.b.intensity.value()), [100, 67, 33, 0]);
+    }
+
+    #[test]
+    fn test_frames() {
+        let samples = (1..=6).map(|i| (Frequency::hz(100).unwrap(), Amplitude::from(i * 10)));
+
+        let grouped = frames(samples, Stereo { a: true, b: false }).collect::<Vec<_>>();
+
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(
+            grouped
+                .iter()
+                .map(|f| f.pulses.map(|p| p.a.intensity.value()))
+                .collect::<Vec<_>>(),
+            [[10, 20, 30, 40], [50, 60, 0, 0]]
+        );
+        assert!(
+            grouped
+                .iter()
+                .flat_map(|f| f.pulses)
+                .all(|p| p.b == Pulse::silent())
+        );
+        // the padding is silent
+        assert_eq!(grouped[1].pulses[2].a, Pulse::silent());
+        assert_eq!(grouped[1].intensity, Pulses::silent().intensity);
+
+        assert_eq!(
+            frames(std::iter::empty(), Stereo::symmetric(true)).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_linear_envelope() {
+        let amplitudes =
+            |envelope: Envelope| (0..4).map(|i| envelope.amplitude(i)).collect::<Vec<_>>();
+
+        assert_eq!(
+            amplitudes(Envelope::Linear { from: 10, to: 40 }),
+            [10, 20, 30, 40]
+        );
+        assert_eq!(
+            amplitudes(Envelope::Linear { from: 100, to: 0 }),
+            [100, 67, 34, 0]
+        );
+    }
+
+    #[test]
+    fn test_patterns() {
+        let bytes = |pattern: Pattern, phase: f32| {
+            Command::SendPulses(pattern.pulses_at(phase, 80))
+                .to_bytes()
+                .unwrap()
+                .to_vec()
+        };
+
+        assert_eq!(
+            bytes(Pattern::Pulse, 0.0),
+            hex!("b00000000a0a0a0a505050500a0a0a0a50505050")
+        );
+        assert_eq!(
+            bytes(Pattern::Pulse, 0.45),
+            hex!("b00000000a0a0a0a505000000a0a0a0a50500000")
+        );
+        assert_eq!(
+            bytes(Pattern::Wave, 0.0),
+            hex!("b000000014141414000002041414141400000204")
+        );
+        assert_eq!(
+            bytes(Pattern::Wave, 0.5),
+            hex!("b00000001414141450504e4c1414141450504e4c")
+        );
+        assert_eq!(
+            bytes(Pattern::Breath, 0.3),
+            hex!("b0000000323232323c41464b323232323c41464b")
+        );
+        assert_eq!(
+            bytes(Pattern::Breath, 1.9),
+            hex!("b000000032323232140f0a0532323232140f0a05")
+        );
+        assert_eq!(
+            bytes(Pattern::Ramp, 0.9),
+            hex!("b00000000a0a0a0a484a4c4e0a0a0a0a484a4c4e")
+        );
+    }
+}
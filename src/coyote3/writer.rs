@@ -0,0 +1,437 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex},
+    task::{Poll, Waker},
+};
+
+use btleplug::{
+    api::{Characteristic, Peripheral as _, WriteType},
+    platform::Peripheral,
+};
+use futures::{
+    StreamExt,
+    channel::{mpsc, oneshot},
+};
+use tracing::{Instrument, Span, debug, info};
+
+use super::{Command, CommandBuffer, Pulses};
+use crate::Result;
+
+/// Writes raw command bytes to the device.
+pub(super) trait Transport: Send + Sync + 'static {
+    fn write(&self, data: &[u8], write_type: WriteType) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// Writes to a characteristic of a [`Peripheral`].
+///
+/// The characteristic is shared with the reconnection task, which replaces it with the one
+/// discovered after reconnecting.
+pub(super) struct PeripheralTransport {
+    pub(super) peripheral: Peripheral,
+    pub(super) characteristic: Arc<Mutex<Characteristic>>,
+}
+
+impl Transport for PeripheralTransport {
+    async fn write(&self, data: &[u8], write_type: WriteType) -> Result<()> {
+        let characteristic = self.characteristic.lock().unwrap().clone();
+        self.peripheral
+            .write(&characteristic, data, write_type)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Only logs what would be written to the wrapped transport, see
+/// [`Coyote3Builder::dry_run()`](super::Coyote3Builder::dry_run).
+pub(super) struct DryRunTransport<T>(pub(super) T);
+
+impl<T: Transport> Transport for DryRunTransport<T> {
+    async fn write(&self, data: &[u8], write_type: WriteType) -> Result<()> {
+        info!(?data, ?write_type, "dry run, not writing command");
+
+        Ok(())
+    }
+}
+
+/// Records everything that is written.
+#[cfg(test)]
+#[derive(Clone, Debug, Default)]
+pub(super) struct RecordingTransport(std::sync::Arc<std::sync::Mutex<Vec<Written>>>);
+
+#[cfg(test)]
+type Written = (Vec<u8>, WriteType);
+
+#[cfg(test)]
+impl RecordingTransport {
+    /// Get the data and write type of each write so far.
+    pub(super) fn written(&self) -> Vec<Written> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl Transport for RecordingTransport {
+    async fn write(&self, data: &[u8], write_type: WriteType) -> Result<()> {
+        self.0.lock().unwrap().push((data.to_vec(), write_type));
+
+        Ok(())
+    }
+}
+
+/// Sends commands from a background task, one after another.
+///
+/// Since the background task finishes each write even if the caller stops waiting for it, a
+/// cancelled [`send()`](Self::send) never leaves a half-written command behind.
+///
+/// At most `depth` commands wait to be written. Pulses without an intensity change that are
+/// still waiting are replaced by the next pulses sent, since only the latest waveform matters,
+/// so sending faster than the device accepts the writes doesn't build up latency.
+#[derive(Clone, Debug)]
+pub(super) struct Writer {
+    queue: Arc<Mutex<Queue>>,
+    /// Wakes the background task for each queued command, and ends it once all clones of the
+    /// writer are dropped.
+    wake: mpsc::UnboundedSender<()>,
+    span: Span,
+}
+
+/// The commands waiting to be written.
+#[derive(Debug)]
+struct Queue {
+    writes: VecDeque<Write>,
+    depth: usize,
+    /// The senders waiting for room in the queue.
+    waiting: Vec<Waker>,
+}
+
+impl Queue {
+    /// Add `write` to the queue, replacing the last command waiting if it can be skipped.
+    ///
+    /// If the queue is full, `write` is handed back.
+    fn push(&mut self, write: Write) -> std::result::Result<(), Write> {
+        if let Some(last) = self.writes.back_mut()
+            && last.supersedable_by(&write)
+        {
+            let stale = std::mem::replace(last, write);
+            // the pulses are superseded rather than failed, the caller might not be waiting
+            let _ = stale.done.send(Ok(()));
+            return Ok(());
+        }
+        if self.writes.len() >= self.depth {
+            return Err(write);
+        }
+
+        self.writes.push_back(write);
+        Ok(())
+    }
+    /// Take the next command to write, making room for a waiting sender.
+    fn pop(&mut self) -> Option<Write> {
+        let write = self.writes.pop_front()?;
+        self.waiting.drain(..).for_each(Waker::wake);
+        Some(write)
+    }
+}
+
+#[derive(Debug)]
+struct Write {
+    command: Command,
+    write_type: WriteType,
+    done: oneshot::Sender<Result<()>>,
+}
+
+impl Write {
+    /// Whether this can be skipped in favor of `next`: pulses without an intensity change
+    /// followed by any pulses.
+    fn supersedable_by(&self, next: &Write) -> bool {
+        matches!(self.command, Command::SendPulses(pulses) if pulses.intensity == Pulses::silent().intensity)
+            && matches!(
+                next.command,
+                Command::SendPulses(_) | Command::SendTrackedPulses { .. }
+            )
+    }
+}
+
+impl Writer {
+    /// Spawn the background task, which ends once all clones of the writer are dropped.
+    ///
+    /// At most `depth` commands wait to be written, at least one. The task serializes the
+    /// commands into a single buffer it reuses. The writes are logged within `span`. This must
+    /// be called from within a Tokio runtime.
+    pub(super) fn spawn(transport: impl Transport, depth: usize, span: Span) -> Self {
+        let queue = Arc::new(Mutex::new(Queue {
+            writes: VecDeque::new(),
+            depth: depth.max(1),
+            waiting: Vec::new(),
+        }));
+        let (wake, mut wakes) = mpsc::unbounded::<()>();
+
+        tokio::spawn({
+            let queue = queue.clone();
+
+            async move {
+                let mut buf = CommandBuffer::new_const();
+
+                while wakes.next().await.is_some() {
+                    let Some(write) = queue.lock().unwrap().pop() else {
+                        continue;
+                    };
+                    let result = match write.command.write_into(&mut buf) {
+                        Ok(()) => transport.write(&buf, write.write_type).await,
+                        Err(e) => Err(e),
+                    };
+                    // the caller might not be waiting anymore
+                    let _ = write.done.send(result);
+                }
+            }
+            .instrument(span.clone())
+        });
+
+        Self { queue, wake, span }
+    }
+    /// Queue the command and wait until it was written, or replaced by later pulses.
+    ///
+    /// If the queue is full, this waits for room first.
+    pub(super) async fn send(&self, command: Command, write_type: WriteType) -> Result<()> {
+        self.span.in_scope(|| debug!(?command));
+
+        let (done, result) = oneshot::channel();
+        let mut write = Some(Write {
+            command,
+            write_type,
+            done,
+        });
+        let queued = std::future::poll_fn(|cx| {
+            let mut queue = self.queue.lock().unwrap();
+            let len = queue.writes.len();
+            match queue.push(write.take().expect("polled after completion")) {
+                Ok(()) => Poll::Ready(queue.writes.len() > len),
+                Err(rejected) => {
+                    write = Some(rejected);
+                    queue.waiting.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        if queued {
+            self.wake
+                .unbounded_send(())
+                .map_err(|_| btleplug::Error::NotConnected)?;
+        }
+
+        result.await.map_err(|_| btleplug::Error::NotConnected)?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        hint::black_box,
+        sync::{Arc, Mutex},
+        time::Instant,
+    };
+
+    use crate::coyote3::DeviceSettings;
+
+    /// Records everything written, in two halves to leave room for a cancellation in between.
+    #[derive(Clone, Default)]
+    struct SlowTransport(Arc<Mutex<Vec<u8>>>);
+
+    impl Transport for SlowTransport {
+        async fn write(&self, data: &[u8], _write_type: WriteType) -> Result<()> {
+            let (first, second) = data.split_at(data.len() / 2);
+            self.0.lock().unwrap().extend_from_slice(first);
+            tokio::task::yield_now().await;
+            self.0.lock().unwrap().extend_from_slice(second);
+
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let transport = RecordingTransport::default();
+        let writer = Writer::spawn(DryRunTransport(transport.clone()), 1, Span::none());
+
+        writer
+            .send(
+                Command::UpdateSettings(DeviceSettings::default()),
+                WriteType::WithResponse,
+            )
+            .await
+            .unwrap();
+        writer
+            .send(
+                Command::SendPulses(Pulses::STOP),
+                WriteType::WithoutResponse,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(transport.written(), []);
+    }
+
+    #[tokio::test]
+    async fn test_reused_buffer() {
+        let transport = RecordingTransport::default();
+        let writer = Writer::spawn(transport.clone(), 1, Span::none());
+
+        // a shorter command after a longer one doesn't carry over any of its bytes
+        let commands = [
+            Command::SendPulses(Pulses::silent()),
+            Command::UpdateSettings(DeviceSettings::default()),
+            Command::SendTrackedPulses {
+                serial: 3,
+                pulses: Pulses::silent(),
+            },
+        ];
+        for command in commands {
+            writer
+                .send(command, WriteType::WithoutResponse)
+                .await
+                .unwrap();
+        }
+
+        let written = transport.written();
+        assert_eq!(written.len(), commands.len());
+        for ((data, _), command) in written.iter().zip(commands) {
+            assert_eq!(**data, *command.to_bytes().unwrap());
+        }
+    }
+
+    /// Records everything written, holding up the first write until it is released.
+    #[derive(Clone)]
+    struct GatedTransport {
+        gate: Arc<Mutex<Option<oneshot::Receiver<()>>>>,
+        recording: RecordingTransport,
+    }
+
+    impl Transport for GatedTransport {
+        async fn write(&self, data: &[u8], write_type: WriteType) -> Result<()> {
+            let gate = self.gate.lock().unwrap().take();
+            if let Some(gate) = gate {
+                let _ = gate.await;
+            }
+            self.recording.write(data, write_type).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pulses_coalesce() {
+        use crate::{
+            Stereo,
+            coyote3::{Amplitude, Frequency, IntensityChange, Pulse},
+        };
+
+        let (release, gate) = oneshot::channel();
+        let transport = GatedTransport {
+            gate: Arc::new(Mutex::new(Some(gate))),
+            recording: RecordingTransport::default(),
+        };
+        let writer = Writer::spawn(transport.clone(), 2, Span::none());
+        let frame = |i: u8| {
+            let pulse = Pulse::new(Frequency::hz(100).unwrap(), Amplitude::from(i));
+            let mut pulses = Pulses::uniform(Pulses::silent().intensity, Stereo::symmetric(pulse));
+            if i == 6 {
+                pulses.intensity = Stereo::symmetric(IntensityChange::RelativeIncrease(1));
+                return Command::SendTrackedPulses { serial: 1, pulses };
+            }
+            Command::SendPulses(pulses)
+        };
+
+        // hold up the link with the first frame
+        let first = tokio::spawn({
+            let writer = writer.clone();
+            async move { writer.send(frame(1), WriteType::WithoutResponse).await }
+        });
+        while transport.gate.lock().unwrap().is_some() {
+            tokio::task::yield_now().await;
+        }
+
+        let sends = futures::future::join_all(
+            (2..=10).map(|i| writer.send(frame(i), WriteType::WithoutResponse)),
+        );
+        let (results, _) = futures::join!(sends, async { release.send(()) });
+        first.await.unwrap().unwrap();
+        assert!(results.into_iter().all(|result| result.is_ok()));
+
+        // only the latest frame is written, but the intensity change isn't skipped
+        let written = transport
+            .recording
+            .written()
+            .into_iter()
+            .map(|(data, _)| data)
+            .collect::<Vec<_>>();
+        let expected = [1, 6, 10].map(|i| frame(i).to_bytes().unwrap().to_vec());
+        assert_eq!(written, expected);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_send() {
+        let transport = SlowTransport::default();
+        let writer = Writer::spawn(transport.clone(), 1, Span::none());
+
+        let pulses = Command::SendPulses(Pulses::silent());
+        let settings = Command::UpdateSettings(DeviceSettings::default());
+
+        let mut send = Box::pin(writer.send(pulses, WriteType::WithoutResponse));
+        assert!(futures::poll!(&mut send).is_pending());
+        drop(send);
+
+        writer
+            .send(settings, WriteType::WithoutResponse)
+            .await
+            .unwrap();
+
+        let expected = [&*pulses.to_bytes().unwrap(), &*settings.to_bytes().unwrap()].concat();
+        assert_eq!(*transport.0.lock().unwrap(), expected);
+    }
+
+    /// Discards everything written.
+    struct NullTransport;
+
+    impl Transport for NullTransport {
+        async fn write(&self, data: &[u8], _write_type: WriteType) -> Result<()> {
+            black_box(data);
+            Ok(())
+        }
+    }
+
+    /// Compares serializing commands into a fresh buffer each, as before, with serializing them
+    /// into a reused one, and measures the whole send path. Run with
+    /// `cargo test --release --lib -- --ignored --nocapture bench_`.
+    #[tokio::test]
+    #[ignore = "benchmark"]
+    async fn bench_send_path() {
+        const COUNT: u32 = 10_000;
+        let command = Command::SendPulses(Pulses::silent());
+
+        let start = Instant::now();
+        for _ in 0..COUNT {
+            black_box(black_box(command).to_bytes().unwrap());
+        }
+        println!("fresh buffer: {:?} per command", start.elapsed() / COUNT);
+
+        let mut buf = CommandBuffer::new_const();
+        let start = Instant::now();
+        for _ in 0..COUNT {
+            black_box(command).write_into(&mut buf).unwrap();
+            black_box(&buf);
+        }
+        println!("reused buffer: {:?} per command", start.elapsed() / COUNT);
+
+        let writer = Writer::spawn(NullTransport, 1, Span::none());
+        let start = Instant::now();
+        for _ in 0..COUNT {
+            writer
+                .send(command, WriteType::WithoutResponse)
+                .await
+                .unwrap();
+        }
+        println!("send(): {:?} per command", start.elapsed() / COUNT);
+    }
+}
@@ -1,3 +1,4 @@
+use btleplug::api::BDAddr;
 use uuid::Uuid;
 
 /// The result type returned by this library.
@@ -11,8 +12,69 @@ pub enum Error {
     ///
     /// This should never occur using an original device.
     MissingCharacteristic(Uuid),
+    /// No matching device could be found while scanning.
+    DeviceNotFound,
+    /// No Bluetooth LE adapter is available.
+    AdapterUnavailable,
+    /// The given peripheral does not have the requested address.
+    AddressMismatch {
+        /// The requested address.
+        expected: BDAddr,
+        /// The address of the given peripheral.
+        actual: BDAddr,
+    },
+    /// The operation did not complete in time.
+    Timeout,
+    /// The device settings are not supported by the device.
+    #[cfg(feature = "coyote3")]
+    InvalidSettings(crate::coyote3::SettingsError),
+    /// The output of a channel would exceed its limit, see
+    /// [`LimitCheck`](crate::coyote3::LimitCheck).
+    #[cfg(feature = "coyote3")]
+    LimitExceeded {
+        /// The channel whose output is too high.
+        channel: crate::Channel,
+        /// The output the pulses would have resulted in.
+        output: u8,
+        /// The limit of the channel.
+        limit: u8,
+    },
+    /// The pulse interval is not a positive multiple of 4 ms.
+    #[cfg(feature = "coyote3")]
+    InvalidPulseInterval(std::time::Duration),
+    /// Pulses can't be sent until the
+    /// [emergency stop](crate::Coyote3::emergency_stop()) is cleared.
+    #[cfg(feature = "coyote3")]
+    EmergencyStopped,
+    /// Pulses can't be sent until the output is [resumed](crate::Coyote3::resume()).
+    #[cfg(feature = "coyote3")]
+    Paused,
+    /// A [recording](crate::Coyote3::record()) could not be written, or a recording to
+    /// [play back](crate::Coyote3::play_file()) could not be read or is invalid.
+    #[cfg(feature = "coyote3")]
+    Recording(std::io::Error),
+    /// A buffer is too short to encode a command into, see
+    /// [`Pulses::write_into()`](crate::coyote3::Pulses::write_into()).
+    #[cfg(feature = "coyote3")]
+    BufferTooSmall {
+        /// The number of bytes the command needs.
+        needed: usize,
+    },
+    /// A packet could not be encoded or a packet received from the device could not be parsed.
+    ///
+    /// This may occur with unknown firmware revisions.
+    Protocol(binrw::Error),
+    /// The device disconnected, e.g. while a command was being written.
+    ///
+    /// The connection can be re-established by connecting again. The [`btleplug`] error that
+    /// indicated the disconnection is preserved as the [source](std::error::Error::source).
+    Disconnected(btleplug::Error),
     /// An error returned by [`btleplug`].
     Btleplug(btleplug::Error),
+    /// The transport of a [relay](crate::coyote3::relay()) failed, e.g. because the connection
+    /// was closed.
+    #[cfg(feature = "relay")]
+    Transport(Box<dyn std::error::Error + Send + Sync>),
 }
 
 impl std::fmt::Display for Error {
@@ -21,7 +83,45 @@ impl std::fmt::Display for Error {
             Error::MissingCharacteristic(uuid) => {
                 write!(f, "missing device characteristic '{uuid}'")
             }
+            Error::DeviceNotFound => write!(f, "no matching device found"),
+            Error::AdapterUnavailable => write!(f, "no bluetooth adapter available"),
+            Error::AddressMismatch { expected, actual } => {
+                write!(f, "expected device address '{expected}', found '{actual}'")
+            }
+            Error::Timeout => write!(f, "operation timed out"),
+            #[cfg(feature = "coyote3")]
+            Error::InvalidSettings(e) => write!(f, "invalid device settings: {e}"),
+            #[cfg(feature = "coyote3")]
+            Error::LimitExceeded {
+                channel,
+                output,
+                limit,
+            } => write!(
+                f,
+                "output {output} of channel {channel:?} exceeds its limit of {limit}"
+            ),
+            #[cfg(feature = "coyote3")]
+            Error::InvalidPulseInterval(interval) => {
+                write!(
+                    f,
+                    "pulse interval {interval:?} is not a positive multiple of 4 ms"
+                )
+            }
+            #[cfg(feature = "coyote3")]
+            Error::EmergencyStopped => write!(f, "output blocked by emergency stop"),
+            #[cfg(feature = "coyote3")]
+            Error::Paused => write!(f, "output is paused"),
+            #[cfg(feature = "coyote3")]
+            Error::Recording(e) => write!(f, "recording failed: {e}"),
+            #[cfg(feature = "coyote3")]
+            Error::BufferTooSmall { needed } => {
+                write!(f, "buffer is too short, {needed} bytes are needed")
+            }
+            Error::Protocol(e) => write!(f, "malformed packet: {e}"),
+            Error::Disconnected(_) => write!(f, "device disconnected"),
             Error::Btleplug(e) => write!(f, "{e}"),
+            #[cfg(feature = "relay")]
+            Error::Transport(e) => write!(f, "relay transport failed: {e}"),
         }
     }
 }
@@ -29,14 +129,89 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            Error::MissingCharacteristic(_) => None,
-            Error::Btleplug(e) => Some(e),
+            Error::MissingCharacteristic(_)
+            | Error::DeviceNotFound
+            | Error::AdapterUnavailable
+            | Error::AddressMismatch { .. }
+            | Error::Timeout => None,
+            #[cfg(feature = "coyote3")]
+            Error::LimitExceeded { .. }
+            | Error::InvalidPulseInterval(_)
+            | Error::EmergencyStopped
+            | Error::Paused
+            | Error::BufferTooSmall { .. } => None,
+            #[cfg(feature = "coyote3")]
+            Error::InvalidSettings(e) => Some(e),
+            #[cfg(feature = "coyote3")]
+            Error::Recording(e) => Some(e),
+            Error::Protocol(e) => Some(e),
+            Error::Disconnected(e) | Error::Btleplug(e) => Some(e),
+            #[cfg(feature = "relay")]
+            Error::Transport(e) => Some(e.as_ref()),
         }
     }
 }
 
+/// Whether `e` means that the device is not connected (anymore).
+fn is_disconnection(e: &btleplug::Error) -> bool {
+    match e {
+        btleplug::Error::NotConnected => true,
+        // BlueZ reports writes to a disconnected device as a D-Bus error
+        btleplug::Error::Other(e) => e.to_string().to_lowercase().contains("not connected"),
+        _ => false,
+    }
+}
+
 impl From<btleplug::Error> for Error {
     fn from(e: btleplug::Error) -> Self {
-        Self::Btleplug(e)
+        if is_disconnection(&e) {
+            Self::Disconnected(e)
+        } else {
+            Self::Btleplug(e)
+        }
+    }
+}
+
+impl From<binrw::Error> for Error {
+    fn from(e: binrw::Error) -> Self {
+        Self::Protocol(e)
+    }
+}
+
+#[cfg(feature = "coyote3")]
+impl From<crate::coyote3::SettingsError> for Error {
+    fn from(e: crate::coyote3::SettingsError) -> Self {
+        Self::InvalidSettings(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn test_disconnected() {
+        let e = Error::from(btleplug::Error::NotConnected);
+        assert!(matches!(
+            e,
+            Error::Disconnected(btleplug::Error::NotConnected)
+        ));
+        assert!(matches!(
+            e.source().unwrap().downcast_ref(),
+            Some(btleplug::Error::NotConnected)
+        ));
+
+        let e = Error::from(btleplug::Error::Other(
+            "org.bluez.Error.Failed: Not connected".into(),
+        ));
+        assert!(matches!(e, Error::Disconnected(btleplug::Error::Other(_))));
+
+        let e = Error::from(btleplug::Error::PermissionDenied);
+        assert!(matches!(
+            e,
+            Error::Btleplug(btleplug::Error::PermissionDenied)
+        ));
     }
 }
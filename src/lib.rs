@@ -2,6 +2,19 @@
 //!
 //! Only the Coyote 3 is supported at the moment. Support for PawPrints is planned for the future,
 //! waiting for their protocol to be stabilized/documented.
+//!
+//! # Runtime
+//!
+//! Connecting to a device requires a Tokio runtime, since the commands are written and the
+//! notifications applied to the state by tasks spawned onto it. So are the tasks of automatic
+//! reconnection and `Coyote3::start_stream()`, and the stop commands sent when a [`StimGuard`] or
+//! a `PulseStream` is dropped. [`btleplug`] needs one on some platforms, too.
+//!
+//! Only the timing is abstracted from Tokio: the periodic sending of pulses, timeouts and
+//! reconnection delays go through a [`Timer`], which is [`TokioTimer`] by default. With another
+//! timer, e.g. of async-std, the Tokio runtime doesn't need its time driver enabled, but it is
+//! still needed for the tasks above. Tokio is not an optional dependency. `Coyote3::scan()`
+//! always uses [`TokioTimer`], use `Coyote3::scan_with_timer()` to pass another one.
 
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 #![deny(missing_docs)]
@@ -18,7 +31,7 @@ pub use btleplug;
 pub use futures_signals;
 
 pub use self::{
-    core::{StateSignal, Stereo},
+    core::{Channel, Device, StateSignal, Stereo, StimGuard, Timer, TokioTimer},
     error::{Error, Result},
 };
 